@@ -0,0 +1,80 @@
+//! Common interface of supported archives, used to process lay/png pairs
+//! without unpacking them.
+
+use super::*;
+use crate::cpk::CpkArchive;
+use crate::mpk::MpkArchive;
+use crate::pairing::lay_stem;
+use std::io::BufReader;
+
+/// Archive with named entries
+pub trait Archive {
+    fn entry_count(&self) -> usize;
+
+    /// Entry name, including the directory if the archive has them
+    fn entry_name(&self, index: usize) -> &str;
+
+    /// Read and decompress entry data, `index` is less than [`Archive::entry_count`]
+    fn read(&mut self, index: usize) -> Result<Vec<u8>, SgSpriteErr>;
+
+    /// Entry by exact name
+    fn find(&self, name: &str) -> Option<usize> {
+        (0..self.entry_count()).find(|i| self.entry_name(*i) == name)
+    }
+
+    /// Lay entries paired with their source image entries by the default [`Pairing`]
+    fn lay_pairs(&self) -> Vec<(usize, Option<usize>)> {
+        self.lay_pairs_with(&Pairing::default())
+    }
+
+    /// Lay entries paired with their source image entries in the same directory,
+    /// the same way lay files are paired with source images in a directory
+    fn lay_pairs_with(&self, pairing: &Pairing) -> Vec<(usize, Option<usize>)> {
+        let entries: Vec<_> = (0..self.entry_count()).map(|i| split_dir(self.entry_name(i))).collect();
+
+        entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (dir, name))| lay_stem(name).map(|n| (i, *dir, n)))
+            .map(|(i, dir, name)| {
+                let png = entries
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (d, _))| *d == dir)
+                    .filter_map(|(p, (_, n))| pairing.rank(n, name).map(|rank| (rank, *n, p)))
+                    .min()
+                    .map(|(_, _, p)| p);
+                (i, png)
+            })
+            .collect()
+    }
+}
+
+/// Entry name split into the directory (with trailing `/`) and the file name
+fn split_dir(name: &str) -> (&str, &str) {
+    match name.rfind('/') {
+        Some(at) => name.split_at(at + 1),
+        None => ("", name),
+    }
+}
+
+/// Archive kind by file extension
+pub fn is_archive(path: &Path) -> bool {
+    archive_ext(path).is_some()
+}
+
+/// Open archive by its file extension, `limits` cap the size of decompressed entries
+pub fn open_archive(path: &Path, limits: &Limits) -> Result<Box<dyn Archive>, SgSpriteErr> {
+    let file = BufReader::new(File::open(path)?);
+
+    match archive_ext(path) {
+        Some("mpk") => Ok(Box::new(MpkArchive::open_with(file, limits)?)),
+        Some("cpk") => Ok(Box::new(CpkArchive::open_with(file, limits)?)),
+        _ => raise!(SgSpriteErr::BadArchive(fmt!("unknown archive type {}", path.display()))),
+    }
+}
+
+fn archive_ext(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?;
+    ["mpk", "cpk"].iter().copied().find(|e| ext.eq_ignore_ascii_case(e))
+}
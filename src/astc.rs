@@ -0,0 +1,600 @@
+//! ASTC (LDR, 2D) block decoder.
+//!
+//! Every block is 128 bits regardless of its footprint. Color endpoint values are
+//! stored from the bottom of the block after the mode and partition fields, weights are
+//! stored bit-reversed from the top, both as integer sequences (mixed bits, trits and quints).
+//! HDR and malformed blocks decode to the error color (magenta), as the spec requires.
+
+use super::*;
+use byteorder::{ByteOrder, LittleEndian};
+use image::{Rgba, RgbaImage};
+
+const BLOCK_SZ: usize = 16;
+const ERROR_COLOR: [u8; 4] = [0xFF, 0, 0xFF, 0xFF];
+const MAX_WEIGHTS: usize = 64;
+
+/// Value ranges of the integer sequence encoding, in quantization level order
+const RANGES: [u32; 21] = [2, 3, 4, 5, 6, 8, 10, 12, 16, 20, 24, 32, 40, 48, 64, 80, 96, 128, 160, 192, 256];
+
+/// Decode blocks of `block_w x block_h` pixels stored row by row
+pub(crate) fn decode_astc(
+    data: &[u8],
+    w: u32,
+    h: u32,
+    block_w: u32,
+    block_h: u32,
+) -> Result<RgbaImage, SgSpriteErr> {
+    let (bx_n, by_n) = (w.div_ceil(block_w) as usize, h.div_ceil(block_h) as usize);
+    let need = bx_n
+        .checked_mul(by_n)
+        .and_then(|n| n.checked_mul(BLOCK_SZ))
+        .ok_or_else(|| SgSpriteErr::BadTexture(fmt!("ASTC image {}x{} is too large", w, h)))?;
+    if data.len() < need {
+        raise!(SgSpriteErr::BadTexture(fmt!(
+            "ASTC {}x{} data is {} bytes, {} expected",
+            block_w,
+            block_h,
+            data.len(),
+            need
+        )));
+    }
+
+    let mut img = RgbaImage::new(w, h);
+    let mut block = vec![[0u8; 4]; (block_w * block_h) as usize];
+
+    for (i, b) in data[..need].chunks_exact(BLOCK_SZ).enumerate() {
+        let bits = LittleEndian::read_u128(b);
+        if decode_block(bits, block_w as usize, block_h as usize, &mut block).is_none() {
+            block.iter_mut().for_each(|px| *px = ERROR_COLOR);
+        }
+
+        let (bx, by) = ((i % bx_n) as u32 * block_w, (i / bx_n) as u32 * block_h);
+        for (p, px) in block.iter().enumerate() {
+            let (x, y) = (bx + p as u32 % block_w, by + p as u32 / block_w);
+            if x < w && y < h {
+                img.put_pixel(x, y, Rgba(*px));
+            }
+        }
+    }
+
+    Ok(img)
+}
+
+fn bits(v: u128, from: u32, n: u32) -> u32 {
+    (v >> from) as u32 & ((1u64 << n) - 1) as u32
+}
+
+/// Weight grid parameters from the block mode
+struct BlockMode {
+    grid_w: usize,
+    grid_h: usize,
+    dual_plane: bool,
+    /// Index in [`RANGES`]
+    weight_range: usize,
+}
+
+fn block_mode(mode: u32) -> Option<BlockMode> {
+    let mut high_precision = mode >> 9 & 1;
+    let mut dual_plane = mode >> 10 & 1;
+    let a = (mode >> 5 & 3) as usize;
+    let mut range = mode >> 4 & 1;
+
+    let (grid_w, grid_h) = if mode & 3 != 0 {
+        range |= (mode & 3) << 1;
+        let b = (mode >> 7 & 3) as usize;
+        match mode >> 2 & 3 {
+            0 => (b + 4, a + 2),
+            1 => (b + 8, a + 2),
+            2 => (a + 2, b + 8),
+            _ if mode & 0x100 != 0 => ((b & 1) + 2, a + 2),
+            _ => (a + 2, (b & 1) + 6),
+        }
+    } else {
+        range |= (mode >> 2 & 3) << 1;
+        if mode >> 2 & 3 == 0 {
+            return None;
+        }
+        let b = (mode >> 9 & 3) as usize;
+        match mode >> 7 & 3 {
+            0 => (12, a + 2),
+            1 => (a + 2, 12),
+            2 => {
+                high_precision = 0;
+                dual_plane = 0;
+                (a + 6, b + 6)
+            }
+            _ => match a {
+                0 => (6, 10),
+                1 => (10, 6),
+                _ => return None,
+            },
+        }
+    };
+
+    Some(BlockMode {
+        grid_w,
+        grid_h,
+        dual_plane: dual_plane != 0,
+        weight_range: (range - 2 + 6 * high_precision) as usize,
+    })
+}
+
+/// Trits, quints and plain bits per value of the range
+fn ise_params(range: usize) -> (u32, u32, u32) {
+    match RANGES[range] {
+        3 => (1, 0, 0),
+        5 => (0, 1, 0),
+        6 => (1, 0, 1),
+        10 => (0, 1, 1),
+        12 => (1, 0, 2),
+        20 => (0, 1, 2),
+        24 => (1, 0, 3),
+        40 => (0, 1, 3),
+        48 => (1, 0, 4),
+        80 => (0, 1, 4),
+        96 => (1, 0, 5),
+        160 => (0, 1, 5),
+        192 => (1, 0, 6),
+        r => (0, 0, r.trailing_zeros()),
+    }
+}
+
+/// Size of `count` values of the range in bits
+fn ise_size(range: usize, count: usize) -> usize {
+    let (trits, quints, bits) = ise_params(range);
+    let bits = bits as usize * count;
+    if trits != 0 {
+        bits + (8 * count).div_ceil(5)
+    } else if quints != 0 {
+        bits + (7 * count).div_ceil(3)
+    } else {
+        bits
+    }
+}
+
+/// Integer sequence reader, bits past the end read as zero
+struct IseReader {
+    data: u128,
+    pos: u32,
+    end: u32,
+}
+
+impl IseReader {
+    fn read(&mut self, n: u32) -> u32 {
+        let avail = self.end.saturating_sub(self.pos).min(n);
+        let v = if avail == 0 { 0 } else { bits(self.data, self.pos, avail) };
+        self.pos += n;
+        v
+    }
+}
+
+/// Decode `count` values, each is `(plain bits, trit or quint)`
+fn decode_ise(r: &mut IseReader, range: usize, count: usize) -> Vec<(u32, u32)> {
+    let (trits, quints, n) = ise_params(range);
+    let mut res = Vec::with_capacity(count + 4);
+
+    while res.len() < count {
+        if trits != 0 {
+            // m0 t[1:0] m1 t[3:2] m2 t[4] m3 t[6:5] m4 t[7]
+            let mut m = [0u32; 5];
+            let mut t = 0;
+            for (i, (shift, tn)) in [(0, 2), (2, 2), (4, 1), (5, 2), (7, 1)].iter().enumerate() {
+                m[i] = r.read(n);
+                t |= r.read(*tn) << shift;
+            }
+            res.extend(m.iter().copied().zip(decode_trits(t).iter().copied()));
+        } else if quints != 0 {
+            // m0 q[2:0] m1 q[4:3] m2 q[6:5]
+            let mut m = [0u32; 3];
+            let mut q = 0;
+            for (i, (shift, qn)) in [(0, 3), (3, 2), (5, 2)].iter().enumerate() {
+                m[i] = r.read(n);
+                q |= r.read(*qn) << shift;
+            }
+            res.extend(m.iter().copied().zip(decode_quints(q).iter().copied()));
+        } else {
+            res.push((r.read(n), 0));
+        }
+    }
+
+    res.truncate(count);
+    res
+}
+
+fn decode_trits(t: u32) -> [u32; 5] {
+    let bit = |v: u32, i: u32| v >> i & 1;
+    let (c, t3, t4);
+
+    if t >> 2 & 7 == 7 {
+        c = (t >> 5 & 7) << 2 | (t & 3);
+        t4 = 2;
+        t3 = 2;
+    } else {
+        c = t & 0x1F;
+        if t >> 5 & 3 == 3 {
+            t4 = 2;
+            t3 = bit(t, 7);
+        } else {
+            t4 = bit(t, 7);
+            t3 = t >> 5 & 3;
+        }
+    }
+
+    let (t0, t1, t2) = if c & 3 == 3 {
+        (bit(c, 3) << 1 | (bit(c, 2) & !bit(c, 3) & 1), bit(c, 4), 2)
+    } else if c >> 2 & 3 == 3 {
+        (c & 3, 2, 2)
+    } else {
+        (bit(c, 1) << 1 | (bit(c, 0) & !bit(c, 1) & 1), c >> 2 & 3, bit(c, 4))
+    };
+
+    [t0, t1, t2, t3, t4]
+}
+
+fn decode_quints(q: u32) -> [u32; 3] {
+    let bit = |v: u32, i: u32| v >> i & 1;
+
+    if q >> 1 & 3 == 3 && q >> 5 & 3 == 0 {
+        let nq0 = !bit(q, 0) & 1;
+        let q2 = bit(q, 0) << 2 | (bit(q, 4) & nq0) << 1 | (bit(q, 3) & nq0);
+        return [4, 4, q2];
+    }
+
+    let (c, q2) = if q >> 1 & 3 == 3 {
+        ((q >> 3 & 3) << 3 | (!(q >> 5) & 3) << 1 | bit(q, 0), 4)
+    } else {
+        (q & 0x1F, q >> 5 & 3)
+    };
+
+    if c & 7 == 5 {
+        [c >> 3 & 3, 4, q2]
+    } else {
+        [c & 7, c >> 3 & 3, q2]
+    }
+}
+
+/// Bits of `m` arranged by `pattern`: `0` is a zero bit, `b` is bit 1 of `m`, `c` is bit 2 and so on
+fn bit_pattern(m: u32, pattern: &str) -> u32 {
+    pattern.bytes().fold(0, |acc, p| {
+        let b = if p == b'0' { 0 } else { m >> (p - b'a') & 1 };
+        acc << 1 | b
+    })
+}
+
+/// Unquantize a color endpoint value to 0..=255
+fn unquantize_color(range: usize, (m, tq): (u32, u32)) -> u32 {
+    let (trits, quints, n) = ise_params(range);
+    if trits == 0 && quints == 0 {
+        return replicate(m, n, 8);
+    }
+
+    let (b, c) = match (trits != 0, n) {
+        (true, 1) => (0, 204),
+        (true, 2) => (bit_pattern(m, "b000b0bb0"), 93),
+        (true, 3) => (bit_pattern(m, "cb000cbcb"), 44),
+        (true, 4) => (bit_pattern(m, "dcb000dcb"), 22),
+        (true, 5) => (bit_pattern(m, "edcb000ed"), 11),
+        (true, _) => (bit_pattern(m, "fedcb000f"), 5),
+        (false, 1) => (0, 113),
+        (false, 2) => (bit_pattern(m, "b0000bb00"), 54),
+        (false, 3) => (bit_pattern(m, "cb0000cbc"), 26),
+        (false, 4) => (bit_pattern(m, "dcb0000dc"), 13),
+        (false, _) => (bit_pattern(m, "edcb0000e"), 6),
+    };
+
+    let a = if m & 1 != 0 { 0x1FF } else { 0 };
+    let t = (tq * c + b) ^ a;
+    (a & 0x80) | (t >> 2)
+}
+
+/// Unquantize a weight to 0..=64
+fn unquantize_weight(range: usize, (m, tq): (u32, u32)) -> u32 {
+    let (trits, quints, n) = ise_params(range);
+
+    let w = if trits == 0 && quints == 0 {
+        replicate(m, n, 6)
+    } else if n == 0 {
+        if trits != 0 { [0, 32, 63][tq as usize] } else { [0, 16, 32, 47, 63][tq as usize] }
+    } else {
+        let (b, c) = match (trits != 0, n) {
+            (true, 1) => (0, 50),
+            (true, 2) => (bit_pattern(m, "b000b0b"), 23),
+            (true, _) => (bit_pattern(m, "cb000cb"), 11),
+            (false, 1) => (0, 28),
+            (false, _) => (bit_pattern(m, "b0000b0"), 13),
+        };
+        let a = if m & 1 != 0 { 0x7F } else { 0 };
+        let t = (tq * c + b) ^ a;
+        (a & 0x20) | (t >> 2)
+    };
+
+    if w > 32 { w + 1 } else { w }
+}
+
+/// Repeat `n`-bit value to fill `to` bits
+fn replicate(v: u32, n: u32, to: u32) -> u32 {
+    if n == 0 {
+        return 0;
+    }
+    let mut res = 0;
+    let mut filled = 0;
+    while filled < to {
+        res = res << n | v;
+        filled += n;
+    }
+    res >> (filled - to)
+}
+
+/// Spread the block's sign bit into the offset, returns `(offset, base)`
+fn bit_transfer_signed(a: u32, b: u32) -> (i32, i32) {
+    let b = (b >> 1) | (a & 0x80);
+    let mut a = ((a >> 1) & 0x3F) as i32;
+    if a & 0x20 != 0 {
+        a -= 0x40;
+    }
+    (a, b as i32)
+}
+
+fn blue_contract(c: [i32; 4]) -> [i32; 4] {
+    [(c[0] + c[2]) >> 1, (c[1] + c[2]) >> 1, c[2], c[3]]
+}
+
+fn clamp(c: [i32; 4]) -> [u32; 4] {
+    let mut res = [0u32; 4];
+    for (r, v) in res.iter_mut().zip(&c) {
+        *r = (*v).clamp(0, 255) as u32;
+    }
+    res
+}
+
+/// LDR endpoint pair of the color endpoint mode, None for HDR modes
+fn endpoints(cem: u32, v: &[u32]) -> Option<[[u32; 4]; 2]> {
+    let v: Vec<i32> = v.iter().map(|x| *x as i32).collect();
+
+    let (e0, e1) = match cem {
+        0 => ([v[0], v[0], v[0], 0xFF], [v[1], v[1], v[1], 0xFF]),
+        1 => {
+            let l0 = (v[0] >> 2) | (v[1] & 0xC0);
+            let l1 = (l0 + (v[1] & 0x3F)).min(0xFF);
+            ([l0, l0, l0, 0xFF], [l1, l1, l1, 0xFF])
+        }
+        4 => ([v[0], v[0], v[0], v[2]], [v[1], v[1], v[1], v[3]]),
+        5 => {
+            let (l_off, l) = bit_transfer_signed(v[1] as u32, v[0] as u32);
+            let (a_off, a) = bit_transfer_signed(v[3] as u32, v[2] as u32);
+            ([l, l, l, a], [l + l_off, l + l_off, l + l_off, a + a_off])
+        }
+        6 | 10 => {
+            let (a0, a1) = if cem == 10 { (v[4], v[5]) } else { (0xFF, 0xFF) };
+            let scale = |c: i32| (c * v[3]) >> 8;
+            ([scale(v[0]), scale(v[1]), scale(v[2]), a0], [v[0], v[1], v[2], a1])
+        }
+        8 | 12 => {
+            let (a0, a1) = if cem == 12 { (v[6], v[7]) } else { (0xFF, 0xFF) };
+            let c0 = [v[0], v[2], v[4], a0];
+            let c1 = [v[1], v[3], v[5], a1];
+            if v[1] + v[3] + v[5] >= v[0] + v[2] + v[4] {
+                (c0, c1)
+            } else {
+                (blue_contract(c1), blue_contract(c0))
+            }
+        }
+        9 | 13 => {
+            let (r_off, r) = bit_transfer_signed(v[1] as u32, v[0] as u32);
+            let (g_off, g) = bit_transfer_signed(v[3] as u32, v[2] as u32);
+            let (b_off, b) = bit_transfer_signed(v[5] as u32, v[4] as u32);
+            let (a_off, a) = if cem == 13 { bit_transfer_signed(v[7] as u32, v[6] as u32) } else { (0, 0xFF) };
+
+            let base = [r, g, b, a];
+            let moved = [r + r_off, g + g_off, b + b_off, a + a_off];
+            if r_off + g_off + b_off >= 0 {
+                (base, moved)
+            } else {
+                (blue_contract(moved), blue_contract(base))
+            }
+        }
+        _ => return None,
+    };
+
+    Some([clamp(e0), clamp(e1)])
+}
+
+/// Partition of the pixel by the spec's hash function
+fn select_partition(seed: u32, x: u32, y: u32, partitions: u32, small_block: bool) -> usize {
+    let (x, y) = if small_block { (x << 1, y << 1) } else { (x, y) };
+    let seed = seed + (partitions - 1) * 1024;
+    let rnum = hash52(seed);
+
+    let mut s = [0u32; 12];
+    for (i, v) in s.iter_mut().enumerate().take(8) {
+        *v = rnum >> (i * 4) & 0xF;
+    }
+    s[8] = rnum >> 18 & 0xF;
+    s[9] = rnum >> 22 & 0xF;
+    s[10] = rnum >> 26 & 0xF;
+    s[11] = rnum.rotate_left(2) & 0xF;
+    for v in s.iter_mut() {
+        *v *= *v;
+    }
+
+    let (sh1, sh2) = if seed & 1 != 0 {
+        (if seed & 2 != 0 { 4 } else { 5 }, if partitions == 3 { 6 } else { 5 })
+    } else {
+        (if partitions == 3 { 6 } else { 5 }, if seed & 2 != 0 { 4 } else { 5 })
+    };
+    let sh3 = if seed & 0x10 != 0 { sh1 } else { sh2 };
+    for (i, v) in s.iter_mut().enumerate() {
+        *v >>= match i {
+            8..=11 => sh3,
+            _ if i % 2 == 0 => sh1,
+            _ => sh2,
+        };
+    }
+
+    let a = (s[0] * x + s[1] * y + (rnum >> 14)) & 0x3F;
+    let b = (s[2] * x + s[3] * y + (rnum >> 10)) & 0x3F;
+    let c = if partitions >= 3 { (s[4] * x + s[5] * y + (rnum >> 6)) & 0x3F } else { 0 };
+    let d = if partitions >= 4 { (s[6] * x + s[7] * y + (rnum >> 2)) & 0x3F } else { 0 };
+
+    if a >= b && a >= c && a >= d {
+        0
+    } else if b >= c && b >= d {
+        1
+    } else if c >= d {
+        2
+    } else {
+        3
+    }
+}
+
+fn hash52(p: u32) -> u32 {
+    let mut p = p;
+    p ^= p >> 15;
+    p = p.wrapping_sub(p << 17);
+    p = p.wrapping_add(p << 7);
+    p = p.wrapping_add(p << 4);
+    p ^= p >> 5;
+    p = p.wrapping_add(p << 16);
+    p ^= p >> 7;
+    p ^= p >> 3;
+    p ^= p << 6;
+    p ^= p >> 17;
+    p
+}
+
+/// Decode a single block, None if it's malformed or HDR
+fn decode_block(b: u128, block_w: usize, block_h: usize, out: &mut [[u8; 4]]) -> Option<()> {
+    // void extent, constant color as UNORM16
+    if bits(b, 0, 9) == 0x1FC {
+        if bits(b, 9, 1) != 0 {
+            return None;
+        }
+        let mut c = [0u8; 4];
+        for (i, ch) in c.iter_mut().enumerate() {
+            *ch = (bits(b, 64 + i as u32 * 16, 16) >> 8) as u8;
+        }
+        out.iter_mut().for_each(|px| *px = c);
+        return Some(());
+    }
+
+    let mode = block_mode(bits(b, 0, 11))?;
+    let planes = if mode.dual_plane { 2 } else { 1 };
+    let weight_count = mode.grid_w * mode.grid_h * planes;
+    if weight_count > MAX_WEIGHTS || mode.grid_w > block_w || mode.grid_h > block_h {
+        return None;
+    }
+    let weight_bits = ise_size(mode.weight_range, weight_count);
+    if !(24..=96).contains(&weight_bits) {
+        return None;
+    }
+
+    let partitions = bits(b, 11, 2) + 1;
+    if mode.dual_plane && partitions == 4 {
+        return None;
+    }
+
+    let mut below_weights = 128 - weight_bits as u32;
+    let (seed, cems, color_start) = if partitions == 1 {
+        (0, vec![bits(b, 13, 4)], 17)
+    } else {
+        let seed = bits(b, 13, 10);
+        let encoded = bits(b, 23, 6);
+        let cems = if encoded & 3 == 0 {
+            vec![encoded >> 2; partitions as usize]
+        } else {
+            let extra_n = 3 * partitions - 4;
+            below_weights -= extra_n;
+            let combined = encoded >> 2 | bits(b, below_weights, extra_n) << 4;
+            let base_class = (encoded & 3) - 1;
+            (0..partitions)
+                .map(|i| {
+                    let class = base_class + (combined >> i & 1);
+                    let m = combined >> (partitions + i * 2) & 3;
+                    class << 2 | m
+                })
+                .collect()
+        };
+        (seed, cems, 29)
+    };
+
+    let ccs = if mode.dual_plane {
+        below_weights -= 2;
+        Some(bits(b, below_weights, 2) as usize)
+    } else {
+        None
+    };
+
+    // color values use the largest range which fits
+    let value_count: usize = cems.iter().map(|c| ((c >> 2) as usize + 1) * 2).sum();
+    if value_count > 18 || below_weights < color_start {
+        return None;
+    }
+    let color_bits = (below_weights - color_start) as usize;
+    let color_range = (0..RANGES.len()).rev().find(|r| ise_size(*r, value_count) <= color_bits)?;
+    if color_range < 4 {
+        return None;
+    }
+
+    let mut reader = IseReader { data: b, pos: color_start, end: below_weights };
+    let values: Vec<u32> = decode_ise(&mut reader, color_range, value_count)
+        .into_iter()
+        .map(|v| unquantize_color(color_range, v))
+        .collect();
+
+    let mut pairs = Vec::with_capacity(cems.len());
+    let mut at = 0;
+    for cem in &cems {
+        let n = ((cem >> 2) as usize + 1) * 2;
+        pairs.push(endpoints(*cem, &values[at..at + n])?);
+        at += n;
+    }
+
+    let mut reader = IseReader { data: b.reverse_bits(), pos: 0, end: weight_bits as u32 };
+    let grid: Vec<u32> = decode_ise(&mut reader, mode.weight_range, weight_count)
+        .into_iter()
+        .map(|v| unquantize_weight(mode.weight_range, v))
+        .collect();
+
+    let small_block = block_w * block_h < 31;
+    let ds = (1024 + block_w / 2) / (block_w - 1).max(1);
+    let dt = (1024 + block_h / 2) / (block_h - 1).max(1);
+
+    for (i, px) in out.iter_mut().enumerate().take(block_w * block_h) {
+        let (x, y) = (i % block_w, i / block_w);
+        let part = if partitions == 1 {
+            0
+        } else {
+            select_partition(seed, x as u32, y as u32, partitions, small_block)
+        };
+        let [e0, e1] = pairs[part];
+
+        // bilinear infill of the weight grid
+        let gs = (ds * x * (mode.grid_w - 1) + 32) >> 6;
+        let gt = (dt * y * (mode.grid_h - 1) + 32) >> 6;
+        let (js, fs, jt, ft) = (gs >> 4, (gs & 0xF) as u32, gt >> 4, (gt & 0xF) as u32);
+        let w11 = (fs * ft + 8) >> 4;
+        let taps = [
+            (js, jt, 16 - fs - ft + w11),
+            (js + 1, jt, fs - w11),
+            (js, jt + 1, ft - w11),
+            (js + 1, jt + 1, w11),
+        ];
+        let weight = |plane: usize| -> u32 {
+            let sum: u32 = taps
+                .iter()
+                .filter(|(_, _, w)| *w != 0)
+                .map(|(sx, sy, w)| grid[(sy * mode.grid_w + sx) * planes + plane] * w)
+                .sum();
+            (sum + 8) >> 4
+        };
+        let (w0, w1) = (weight(0), if mode.dual_plane { weight(1) } else { 0 });
+
+        for ch in 0..4 {
+            let w = if ccs == Some(ch) { w1 } else { w0 };
+            let (c0, c1) = (e0[ch] * 257, e1[ch] * 257);
+            px[ch] = (((c0 * (64 - w) + c1 * w + 32) >> 6) >> 8) as u8;
+        }
+    }
+
+    Some(())
+}
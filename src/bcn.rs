@@ -0,0 +1,391 @@
+//! BCn (DXTn and BPTC) block decoders.
+
+use super::*;
+use byteorder::{ByteOrder, LittleEndian};
+use image::{Rgba, RgbaImage};
+
+/// Decoded 4x4 block, row-major
+pub(crate) type Block = [[u8; 4]; 16];
+
+/// Decoder of a single block of the format
+pub(crate) struct BlockFormat {
+    pub name: &'static str,
+    pub block_sz: usize,
+    pub decode: fn(&[u8], &mut Block),
+}
+
+pub(crate) const BC1: BlockFormat = BlockFormat { name: "BC1", block_sz: 8, decode: decode_bc1 };
+pub(crate) const BC2: BlockFormat = BlockFormat { name: "BC2", block_sz: 16, decode: decode_bc2 };
+pub(crate) const BC3: BlockFormat = BlockFormat { name: "BC3", block_sz: 16, decode: decode_bc3 };
+pub(crate) const BC7: BlockFormat = BlockFormat { name: "BC7", block_sz: 16, decode: decode_bc7 };
+
+/// Size of `w x h` image data in blocks of the format, None if it overflows
+pub(crate) fn data_size(f: &BlockFormat, w: u32, h: u32) -> Option<usize> {
+    blocks(w).checked_mul(blocks(h))?.checked_mul(f.block_sz)
+}
+
+/// Number of 4 px blocks covering `px` pixels
+pub(crate) fn blocks(px: u32) -> usize {
+    (px as usize).div_ceil(4)
+}
+
+/// Decode blocks stored row by row, the last row/column of blocks may be cropped
+pub(crate) fn decode_image(f: &BlockFormat, data: &[u8], w: u32, h: u32) -> Result<RgbaImage, SgSpriteErr> {
+    let need = data_size(f, w, h)
+        .ok_or_else(|| SgSpriteErr::BadTexture(fmt!("{} image {}x{} is too large", f.name, w, h)))?;
+    if data.len() < need {
+        raise!(SgSpriteErr::BadTexture(fmt!("{} data is {} bytes, {} expected", f.name, data.len(), need)));
+    }
+
+    let mut img = RgbaImage::new(w, h);
+    let mut block = [[0u8; 4]; 16];
+    let bw = blocks(w);
+
+    for (i, b) in data[..need].chunks_exact(f.block_sz).enumerate() {
+        (f.decode)(b, &mut block);
+
+        let (bx, by) = ((i % bw) as u32 * 4, (i / bw) as u32 * 4);
+        for (p, px) in block.iter().enumerate() {
+            let (x, y) = (bx + p as u32 % 4, by + p as u32 / 4);
+            if x < w && y < h {
+                img.put_pixel(x, y, Rgba(*px));
+            }
+        }
+    }
+
+    Ok(img)
+}
+
+fn rgb565(c: u16) -> [u8; 4] {
+    let (r, g, b) = ((c >> 11) as u8 & 0x1F, (c >> 5) as u8 & 0x3F, c as u8 & 0x1F);
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2, 0xFF]
+}
+
+fn mix(a: [u8; 4], b: [u8; 4], wa: u32, wb: u32) -> [u8; 4] {
+    let mut res = [0u8; 4];
+    for (i, c) in res.iter_mut().enumerate() {
+        *c = ((a[i] as u32 * wa + b[i] as u32 * wb) / (wa + wb)) as u8;
+    }
+    res
+}
+
+/// Color part shared by BC1-3, `alpha_mode` allows 3-color + transparent palette
+fn decode_color(b: &[u8], block: &mut Block, alpha_mode: bool) {
+    let (c0, c1) = (LittleEndian::read_u16(&b[0..]), LittleEndian::read_u16(&b[2..]));
+    let (p0, p1) = (rgb565(c0), rgb565(c1));
+
+    let palette = if c0 > c1 || !alpha_mode {
+        [p0, p1, mix(p0, p1, 2, 1), mix(p0, p1, 1, 2)]
+    } else {
+        [p0, p1, mix(p0, p1, 1, 1), [0, 0, 0, 0]]
+    };
+
+    let indices = LittleEndian::read_u32(&b[4..]);
+    for (i, px) in block.iter_mut().enumerate() {
+        *px = palette[(indices >> (i * 2) & 3) as usize];
+    }
+}
+
+fn decode_bc1(b: &[u8], block: &mut Block) {
+    decode_color(b, block, true);
+}
+
+fn decode_bc2(b: &[u8], block: &mut Block) {
+    decode_color(&b[8..], block, false);
+
+    let alpha = LittleEndian::read_u64(b);
+    for (i, px) in block.iter_mut().enumerate() {
+        px[3] = (alpha >> (i * 4) & 0xF) as u8 * 0x11;
+    }
+}
+
+/// Interpolated 8-value alpha block, shared by BC3 and BC4/5
+pub(crate) fn decode_alpha(b: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (b[0] as u32, b[1] as u32);
+    let mut palette = [a0, a1, 0, 0, 0, 0, 0, 0xFF];
+
+    if a0 > a1 {
+        for (i, a) in palette.iter_mut().enumerate().skip(2) {
+            *a = ((8 - i as u32) * a0 + (i as u32 - 1) * a1) / 7;
+        }
+    } else {
+        for (i, a) in palette.iter_mut().enumerate().skip(2).take(4) {
+            *a = ((6 - i as u32) * a0 + (i as u32 - 1) * a1) / 5;
+        }
+    }
+
+    let indices = LittleEndian::read_u48(&b[2..]);
+    let mut res = [0u8; 16];
+    for (i, a) in res.iter_mut().enumerate() {
+        *a = palette[(indices >> (i * 3) & 7) as usize] as u8;
+    }
+    res
+}
+
+fn decode_bc3(b: &[u8], block: &mut Block) {
+    decode_color(&b[8..], block, false);
+
+    for (px, a) in block.iter_mut().zip(&decode_alpha(b)) {
+        px[3] = *a;
+    }
+}
+
+/// BC7 mode parameters
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// P-bit per endpoint
+    endpoint_pbits: bool,
+    /// P-bit per subset
+    shared_pbits: bool,
+    index_bits: u32,
+    index2_bits: u32,
+}
+
+macro_rules! bc7_modes {
+    ($([$ns:expr, $pb:expr, $rb:expr, $isb:expr, $cb:expr, $ab:expr, $epb:expr, $spb:expr, $ib:expr, $ib2:expr]),*) => {
+        [$(Bc7Mode {
+            subsets: $ns,
+            partition_bits: $pb,
+            rotation_bits: $rb,
+            index_selection_bits: $isb,
+            color_bits: $cb,
+            alpha_bits: $ab,
+            endpoint_pbits: $epb != 0,
+            shared_pbits: $spb != 0,
+            index_bits: $ib,
+            index2_bits: $ib2,
+        }),*]
+    };
+}
+
+const BC7_MODES: [Bc7Mode; 8] = bc7_modes![
+    [3, 4, 0, 0, 4, 0, 1, 0, 3, 0],
+    [2, 6, 0, 0, 6, 0, 0, 1, 3, 0],
+    [3, 6, 0, 0, 5, 0, 0, 0, 2, 0],
+    [2, 6, 0, 0, 7, 0, 1, 0, 2, 0],
+    [1, 0, 2, 1, 5, 6, 0, 0, 2, 3],
+    [1, 0, 2, 0, 7, 8, 0, 0, 2, 2],
+    [1, 0, 0, 0, 7, 7, 1, 0, 4, 0],
+    [2, 6, 0, 0, 5, 5, 1, 0, 2, 0]
+];
+
+const BC7_WEIGHTS2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// 2-subset partitions, bit `i` is the subset of pixel `i`
+const BC7_PARTITIONS2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80,
+    0xC800, 0xFFEC, 0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000,
+    0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C,
+    0xAAAA, 0xF0F0, 0x5A5A, 0x33CC, 0x3C3C, 0x55AA, 0x9696, 0xA55A,
+    0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C,
+    0x9336, 0x9CC6, 0x817E, 0xE718, 0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+/// 3-subset partitions
+const BC7_PARTITIONS3: [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2], [0, 0, 0, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 2, 0, 0, 1, 2, 2, 1, 1, 2, 2, 1, 1], [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2], [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1], [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2], [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2], [0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2],
+    [0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2], [0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2, 1, 2, 2, 2], [0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0, 2, 2, 2, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2], [0, 1, 1, 1, 0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0],
+    [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2], [0, 0, 2, 2, 0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, 2, 2, 2], [0, 0, 0, 1, 0, 0, 0, 1, 2, 2, 2, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2], [0, 0, 0, 0, 1, 1, 0, 0, 2, 2, 1, 0, 2, 2, 1, 0],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1, 0, 0, 0, 0], [0, 0, 1, 2, 0, 0, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 0], [0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1],
+    [0, 0, 2, 2, 1, 1, 0, 2, 1, 1, 0, 2, 0, 0, 2, 2], [0, 1, 1, 0, 0, 1, 1, 0, 2, 0, 0, 2, 2, 2, 2, 2],
+    [0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1], [0, 0, 0, 0, 2, 0, 0, 0, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 2, 2, 2], [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 2, 0, 0, 2, 2, 0, 2, 2, 2], [0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0], [0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0],
+    [0, 1, 2, 0, 2, 0, 1, 2, 1, 2, 0, 1, 0, 1, 2, 0], [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, 1, 1], [0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1], [0, 0, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 1, 1], [0, 2, 2, 0, 1, 2, 2, 1, 0, 2, 2, 0, 1, 2, 2, 1],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 1, 0, 1], [0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2], [0, 2, 2, 2, 0, 1, 1, 1, 0, 2, 2, 2, 0, 1, 1, 1],
+    [0, 0, 0, 2, 1, 1, 1, 2, 0, 0, 0, 2, 1, 1, 1, 2], [0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2], [0, 0, 0, 2, 1, 1, 1, 2, 1, 1, 1, 2, 0, 0, 0, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2], [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2], [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2],
+    [0, 0, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2],
+    [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1], [0, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 1, 2, 2, 2],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2], [0, 1, 1, 1, 2, 0, 1, 1, 2, 2, 0, 1, 2, 2, 2, 0],
+];
+
+/// Anchor pixel of the second subset of 2-subset partitions
+const BC7_ANCHORS2: [usize; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6,
+    6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+/// Anchor pixels of the second and the third subsets of 3-subset partitions
+const BC7_ANCHORS3: [[usize; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3,
+        3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5, 15, 15,
+        8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15,
+        3, 15, 5, 5, 5, 8, 5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8,
+        15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6, 10, 15, 15, 10, 8,
+        15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8,
+        15, 3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+/// LSB-first reader of a 128-bit block
+struct BlockBits {
+    bits: u128,
+}
+
+impl BlockBits {
+    fn read(&mut self, n: u32) -> u32 {
+        let v = (self.bits & ((1 << n) - 1)) as u32;
+        self.bits >>= n;
+        v
+    }
+}
+
+fn bc7_weights(bits: u32) -> &'static [u32] {
+    match bits {
+        2 => &BC7_WEIGHTS2,
+        3 => &BC7_WEIGHTS3,
+        _ => &BC7_WEIGHTS4,
+    }
+}
+
+/// Expand `bits`-wide value to 8 bits
+fn bc7_expand(v: u32, bits: u32) -> u32 {
+    let v = v << (8 - bits);
+    v | v >> bits
+}
+
+fn decode_bc7(b: &[u8], block: &mut Block) {
+    let mut bits = BlockBits { bits: LittleEndian::read_u128(b) };
+
+    let mode_n = b[0].trailing_zeros() as usize;
+    let mode = match BC7_MODES.get(mode_n) {
+        Some(m) => m,
+        None => {
+            // reserved mode
+            *block = [[0; 4]; 16];
+            return;
+        }
+    };
+    bits.read(mode_n as u32 + 1);
+
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    // [subset][endpoint][channel]
+    let mut endpoints = [[[0u32; 4]; 2]; 3];
+    for ch in 0..3 {
+        for e in endpoints.iter_mut().take(mode.subsets).flat_map(|s| s.iter_mut()) {
+            e[ch] = bits.read(mode.color_bits);
+        }
+    }
+    for e in endpoints.iter_mut().take(mode.subsets).flat_map(|s| s.iter_mut()) {
+        e[3] = bits.read(mode.alpha_bits);
+    }
+
+    let mut pbits = [[0u32; 2]; 3];
+    if mode.endpoint_pbits {
+        for p in pbits.iter_mut().take(mode.subsets).flat_map(|s| s.iter_mut()) {
+            *p = bits.read(1);
+        }
+    } else if mode.shared_pbits {
+        for p in pbits.iter_mut().take(mode.subsets) {
+            let bit = bits.read(1);
+            *p = [bit, bit];
+        }
+    }
+
+    let has_pbits = mode.endpoint_pbits || mode.shared_pbits;
+    for (s, subset) in endpoints.iter_mut().enumerate().take(mode.subsets) {
+        for (e, endpoint) in subset.iter_mut().enumerate() {
+            for (ch, v) in endpoint.iter_mut().enumerate() {
+                let mut n = if ch == 3 { mode.alpha_bits } else { mode.color_bits };
+                if n == 0 {
+                    *v = 0xFF;
+                    continue;
+                }
+                if has_pbits {
+                    *v = *v << 1 | pbits[s][e];
+                    n += 1;
+                }
+                *v = bc7_expand(*v, n);
+            }
+        }
+    }
+
+    let subset_of = |i: usize| -> usize {
+        match mode.subsets {
+            2 => (BC7_PARTITIONS2[partition] >> i & 1) as usize,
+            3 => BC7_PARTITIONS3[partition][i] as usize,
+            _ => 0,
+        }
+    };
+    let is_anchor = |i: usize| -> bool {
+        match mode.subsets {
+            2 => i == 0 || i == BC7_ANCHORS2[partition],
+            3 => i == 0 || i == BC7_ANCHORS3[0][partition] || i == BC7_ANCHORS3[1][partition],
+            _ => i == 0,
+        }
+    };
+
+    // anchor pixels have the top index bit implicitly zero
+    let mut indices = [0u32; 16];
+    for (i, idx) in indices.iter_mut().enumerate() {
+        *idx = bits.read(mode.index_bits - is_anchor(i) as u32);
+    }
+    let mut indices2 = [0u32; 16];
+    if mode.index2_bits > 0 {
+        for (i, idx) in indices2.iter_mut().enumerate() {
+            *idx = bits.read(mode.index2_bits - (i == 0) as u32);
+        }
+    }
+
+    for (i, px) in block.iter_mut().enumerate() {
+        let [e0, e1] = endpoints[subset_of(i)];
+
+        // color and alpha weights, modes 4 and 5 have separate alpha indices
+        let (cw, aw) = if mode.index2_bits == 0 {
+            let w = bc7_weights(mode.index_bits)[indices[i] as usize];
+            (w, w)
+        } else {
+            let primary = bc7_weights(mode.index_bits)[indices[i] as usize];
+            let secondary = bc7_weights(mode.index2_bits)[indices2[i] as usize];
+            if index_selection == 0 { (primary, secondary) } else { (secondary, primary) }
+        };
+
+        for ch in 0..4 {
+            let w = if ch == 3 { aw } else { cw };
+            px[ch] = ((e0[ch] * (64 - w) + e1[ch] * w + 32) >> 6) as u8;
+        }
+
+        match rotation {
+            1 => px.swap(0, 3),
+            2 => px.swap(1, 3),
+            3 => px.swap(2, 3),
+            _ => (),
+        }
+    }
+}
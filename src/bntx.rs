@@ -0,0 +1,168 @@
+//! Nintendo Switch `.bntx` texture decoder.
+//!
+//! Layout (little-endian):
+//!
+//! - header, `0x20` bytes: `[4:"BNTX"][4:padding][u32:version][u16:bom][u8:alignment][u8:addr_size]`
+//!   `[u32:name][u16:flags][u16:first_block][u32:relocations][u32:file_size]`
+//! - `NX` header at `0x20`: `[4:"NX  "][u32:texture_count][u64:info_table]...`,
+//!   info table is an array of `u64` offsets of `BRTI` blocks
+//! - `BRTI` (texture info): `[4:"BRTI"][u32:next][u32:size][4:reserved][u8:flags][u8:dim][u16:tile_mode]`
+//!   `[u16:swizzle][u16:mip_count][u16:sample_count][2:padding][u32:format][u32:access][u32:width]`
+//!   `[u32:height][u32:depth][u32:array_len][u32:layout][u32:layout2][0x14:reserved][u32:image_size]`
+//!   `[u32:alignment][u32:channels][u8:image_dim][3:padding][u64:name][u64:parent][u64:mip_table]`,
+//!   mip table is an array of `u64` mip data offsets
+//!
+//! Only the top mip level of the first texture is decoded. Block-linear textures use
+//! `1 << (layout & 7)` GOBs per block. Supported formats are R8G8B8A8, B8G8R8A8, BC1-3, BC7 and ASTC.
+
+use super::*;
+use crate::astc::decode_astc;
+use crate::bcn::{self, BlockFormat};
+use crate::swizzle::deswizzle_block_linear;
+use byteorder::{ByteOrder, LittleEndian};
+use image::RgbaImage;
+
+const MAGIC: &[u8; 4] = b"BNTX";
+const NX_MAGIC: &[u8; 4] = b"NX  ";
+const BRTI_MAGIC: &[u8; 4] = b"BRTI";
+const BRTI_SZ: usize = 0xA0;
+
+const TILE_MODE_LINEAR: u16 = 1;
+
+/// Texture info block fields
+struct TextureInfo {
+    tile_mode: u16,
+    format: u32,
+    width: u32,
+    height: u32,
+    block_height_log2: u32,
+    image_size: usize,
+    data_offset: usize,
+}
+
+enum BntxFormat {
+    /// 4 bytes per pixel, red and blue are swapped if set
+    Rgba(bool),
+    Blocks(&'static BlockFormat),
+    /// Block footprint
+    Astc(u32, u32),
+}
+
+impl BntxFormat {
+    /// Block footprint and size in bytes
+    fn block(&self) -> (u32, u32, usize) {
+        match self {
+            BntxFormat::Rgba(_) => (1, 1, 4),
+            BntxFormat::Blocks(f) => (4, 4, f.block_sz),
+            BntxFormat::Astc(w, h) => (*w, *h, 16),
+        }
+    }
+}
+
+fn bad(msg: impl Into<String>) -> SgSpriteErr {
+    SgSpriteErr::BadTexture(msg.into())
+}
+
+/// Data starts with the BNTX magic
+pub fn is_bntx(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Size of the first texture
+pub fn bntx_dimensions(data: &[u8]) -> Result<(u32, u32), SgSpriteErr> {
+    let tex = first_texture(data)?;
+    Ok((tex.width, tex.height))
+}
+
+/// Decode the top mip level of the first texture of the BNTX file
+pub fn decode_bntx(data: &[u8]) -> Result<RgbaImage, SgSpriteErr> {
+    let tex = first_texture(data)?;
+    let format = texture_format(tex.format)?;
+    let (w, h) = (tex.width, tex.height);
+
+    let tex_data = data
+        .get(tex.data_offset..tex.data_offset.saturating_add(tex.image_size))
+        .ok_or_else(|| bad("texture data is out of file"))?;
+
+    let (block_w, block_h, block_sz) = format.block();
+    let (bx_n, by_n) = (w.div_ceil(block_w) as usize, h.div_ceil(block_h) as usize);
+    let size = bx_n.checked_mul(by_n).and_then(|n| n.checked_mul(block_sz));
+    let size = size.ok_or_else(|| bad(fmt!("texture {}x{} is too large", w, h)))?;
+    let blocks = if tex.tile_mode == TILE_MODE_LINEAR {
+        tex_data.to_vec()
+    } else {
+        deswizzle_block_linear(tex_data, bx_n, by_n, block_sz, 1 << tex.block_height_log2)
+            .ok_or_else(|| bad("block-linear data is too short"))?
+    };
+
+    match format {
+        BntxFormat::Rgba(swap) => {
+            let mut px = blocks.get(..size).ok_or_else(|| bad("RGBA data is too short"))?.to_vec();
+            if swap {
+                px.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+            }
+            Ok(RgbaImage::from_raw(w, h, px).expect("pixel buffer size"))
+        }
+        BntxFormat::Blocks(f) => bcn::decode_image(f, &blocks, w, h),
+        BntxFormat::Astc(bw, bh) => decode_astc(&blocks, w, h, bw, bh),
+    }
+}
+
+/// Format by its type (high byte), the low byte is the channel type (UNORM, SRGB, etc)
+fn texture_format(format: u32) -> Result<BntxFormat, SgSpriteErr> {
+    const ASTC: [(u32, u32); 14] = [
+        (4, 4), (5, 4), (5, 5), (6, 5), (6, 6), (8, 5), (8, 6),
+        (8, 8), (10, 5), (10, 6), (10, 8), (10, 10), (12, 10), (12, 12),
+    ];
+
+    let res = match format >> 8 {
+        0x0B => BntxFormat::Rgba(false),
+        0x0C => BntxFormat::Rgba(true),
+        0x1A => BntxFormat::Blocks(&bcn::BC1),
+        0x1B => BntxFormat::Blocks(&bcn::BC2),
+        0x1C => BntxFormat::Blocks(&bcn::BC3),
+        0x20 => BntxFormat::Blocks(&bcn::BC7),
+        t @ 0x2D..=0x3A => {
+            let (w, h) = ASTC[(t - 0x2D) as usize];
+            BntxFormat::Astc(w, h)
+        }
+        _ => raise!(bad(fmt!("unsupported BNTX texture format {:#06X}", format))),
+    };
+    Ok(res)
+}
+
+fn read_u64_at(data: &[u8], at: usize) -> Result<usize, SgSpriteErr> {
+    let v = data.get(at..at.saturating_add(8)).ok_or_else(|| bad(fmt!("offset {:#X} is out of file", at)))?;
+    Ok(LittleEndian::read_u64(v) as usize)
+}
+
+fn first_texture(data: &[u8]) -> Result<TextureInfo, SgSpriteErr> {
+    if !is_bntx(data) {
+        raise!(bad("not a BNTX file"));
+    }
+    if data.len() < 0x30 || &data[0x20..0x24] != NX_MAGIC {
+        raise!(bad("truncated BNTX header"));
+    }
+    if LittleEndian::read_u16(&data[0xC..]) != 0xFEFF {
+        raise!(bad("big-endian BNTX is not supported"));
+    }
+    if LittleEndian::read_u32(&data[0x24..]) == 0 {
+        raise!(bad("BNTX has no textures"));
+    }
+
+    let info_at = read_u64_at(data, read_u64_at(data, 0x28)?)?;
+    let t = data.get(info_at..info_at.saturating_add(BRTI_SZ)).ok_or_else(|| bad("texture info is out of file"))?;
+    if &t[..4] != BRTI_MAGIC {
+        raise!(bad("texture info is not a BRTI block"));
+    }
+
+    Ok(TextureInfo {
+        tile_mode: LittleEndian::read_u16(&t[0x12..]),
+        format: LittleEndian::read_u32(&t[0x1C..]),
+        width: LittleEndian::read_u32(&t[0x24..]),
+        height: LittleEndian::read_u32(&t[0x28..]),
+        block_height_log2: LittleEndian::read_u32(&t[0x34..]) & 7,
+        image_size: LittleEndian::read_u32(&t[0x50..]) as usize,
+        data_offset: read_u64_at(data, LittleEndian::read_u64(&t[0x70..]) as usize)?,
+    })
+}
@@ -0,0 +1,230 @@
+//! Format anomaly checks, used by `--check` mode.
+
+use super::*;
+use crate::validate::{sprite_chunks, src_pos};
+use std::fmt::{self, Display, Formatter};
+
+/// Anomaly found in a lay file. Anomalies don't prevent drawing
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Anomaly {
+    /// `B` or `C` info byte differs from what's usual for the sprite type
+    UnexpectedHead { sprite: usize, info: [u8; 4] },
+    /// Sprite type byte `D` isn't known, such sprites are drawn standalone
+    UnknownType { sprite: usize, type_id: u8 },
+    /// Dep sprite refers to a sub sprite id that doesn't exist
+    MissingSub { sprite: usize, sub_id: u8 },
+    NoBase,
+    /// Base sprite isn't the first one, so subs don't depend on it
+    MisplacedBase { sprite: usize },
+    MultipleBase { sprites: Vec<usize> },
+    /// Sprite chunk range exceeds the chunk list
+    BadChunkRange { sprite: usize },
+    /// Destination blocks of two chunks of the same sprite overlap
+    OverlappingChunks { sprite: usize, chunks: (usize, usize) },
+    /// Chunks not referenced by any sprite
+    UnusedChunks { first: usize, count: usize },
+    /// Chunk block doesn't fit into the paired png
+    ChunkOutOfSource { chunk: usize, x: i64, y: i64 },
+    /// None of the sprite chunks is on the game screen
+    OffScreen { sprite: usize },
+    NoSourcePng,
+}
+
+impl Display for Anomaly {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use Anomaly::*;
+        match self {
+            UnexpectedHead { sprite, info } => {
+                write!(f, "sprite {}: unexpected info bytes {:X}", sprite, Hex(info))
+            }
+            UnknownType { sprite, type_id } => write!(f, "sprite {}: unknown type {:#04X}", sprite, type_id),
+            MissingSub { sprite, sub_id } => write!(f, "sprite {}: sub sprite {} doesn't exist", sprite, sub_id),
+            NoBase => f.write_str("no base sprite"),
+            MisplacedBase { sprite } => write!(f, "sprite {}: base sprite isn't the first one", sprite),
+            MultipleBase { sprites } => write!(f, "multiple base sprites: {:?}", sprites),
+            BadChunkRange { sprite } => write!(f, "sprite {}: chunk range is out of chunk list", sprite),
+            OverlappingChunks { sprite, chunks: (a, b) } => {
+                write!(f, "sprite {}: chunks {} and {} overlap", sprite, a, b)
+            }
+            UnusedChunks { first, count } => {
+                write!(f, "chunks {}..{} aren't used by any sprite", first, first + count)
+            }
+            ChunkOutOfSource { chunk, x, y } => write!(f, "chunk {} at {},{} is out of source png", chunk, x, y),
+            OffScreen { sprite } => write!(f, "sprite {}: all chunks are off screen", sprite),
+            NoSourcePng => f.write_str("no source png"),
+        }
+    }
+}
+
+/// Check results for a single file
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LayReport {
+    pub path: PathBuf,
+    pub anomalies: Vec<Anomaly>,
+    /// Set if the file can't be parsed
+    pub error: Option<String>,
+}
+
+/// Parse the lay file and check it along with its source png, found by the default [`Pairing`].
+/// Sprites are checked against the screen of the detected game profile, if any.
+/// Unknown sprite types are reported as anomalies if `opts` keep them as standalone
+pub fn check_file(lay_file: &Path, opts: &ParseOpts) -> Result<Vec<Anomaly>, SgSpriteErr> {
+    let lay = opts.parse_lay(&mut File::open(lay_file)?)?;
+
+    let src_dim = match Pairing::default().find_source(lay_file) {
+        Ok(src) => Some(source_dimensions(&std::fs::read(src)?)?),
+        Err(SgSpriteErr::NoSourcePng(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    let screen = detect_profile(&lay).map(|g| g.screen);
+    Ok(check_with_source(&lay, src_dim, screen))
+}
+
+/// Same as [`check_lay`], reporting missing source png as an anomaly
+/// and checking against the screen if it's known
+pub(crate) fn check_with_source(
+    lay: &ParsedLay,
+    src_dim: Option<(u32, u32)>,
+    screen: Option<(u32, u32)>,
+) -> Vec<Anomaly> {
+    let mut anomalies = check_lay(lay, src_dim);
+    if src_dim.is_none() {
+        anomalies.push(Anomaly::NoSourcePng);
+    }
+    if let Some(screen) = screen {
+        anomalies.extend(check_screen(lay, screen));
+    }
+
+    anomalies
+}
+
+/// Check parsed lay, source png checks are skipped if `src_dim` is None
+pub fn check_lay(lay: &ParsedLay, src_dim: Option<(u32, u32)>) -> Vec<Anomaly> {
+    let mut res = Vec::new();
+    let mut bases = Vec::new();
+    let mut chunk_used = vec![false; lay.chunks.len()];
+
+    for (i, s) in lay.sprites.iter().enumerate() {
+        let [_, b, c, _] = s.info;
+        let usual_head = match s.sprite_type {
+            SpriteT::Base | SpriteT::Sub => b == 0 && c == 0,
+            SpriteT::Dep { .. } => c == 0,
+            SpriteT::Overlay => b == 0 && c == 0x10,
+            SpriteT::Unknown(type_id) => {
+                res.push(Anomaly::UnknownType { sprite: i, type_id });
+                true
+            }
+        };
+
+        if !usual_head {
+            res.push(Anomaly::UnexpectedHead { sprite: i, info: s.info });
+        }
+
+        match s.sprite_type {
+            SpriteT::Base => bases.push(i),
+            SpriteT::Dep { depends_on, .. } if !lay.sub_map.contains_key(&depends_on) => {
+                res.push(Anomaly::MissingSub { sprite: i, sub_id: depends_on })
+            }
+            _ => (),
+        }
+
+        let chunks = match sprite_chunks(lay, s, i) {
+            Ok(c) => c,
+            Err(_) => {
+                res.push(Anomaly::BadChunkRange { sprite: i });
+                continue;
+            }
+        };
+
+        chunk_used[s.chunk_offset..s.chunk_offset + s.chunk_count]
+            .iter_mut()
+            .for_each(|u| *u = true);
+
+        for (a, ca) in chunks.iter().enumerate() {
+            for (b, cb) in chunks.iter().enumerate().skip(a + 1) {
+                let block = lay.block_size as i64;
+                let overlap = (ca.img_x as i64 - cb.img_x as i64).abs() < block
+                    && (ca.img_y as i64 - cb.img_y as i64).abs() < block;
+
+                if overlap {
+                    let chunks = (s.chunk_offset + a, s.chunk_offset + b);
+                    res.push(Anomaly::OverlappingChunks { sprite: i, chunks });
+                }
+            }
+        }
+    }
+
+    match bases.as_slice() {
+        [] => res.push(Anomaly::NoBase),
+        [0] => (),
+        [b] => res.push(Anomaly::MisplacedBase { sprite: *b }),
+        _ => res.push(Anomaly::MultipleBase { sprites: bases }),
+    }
+
+    let mut i = 0;
+    while i < chunk_used.len() {
+        let count = chunk_used[i..].iter().take_while(|u| !**u).count();
+        if count > 0 {
+            res.push(Anomaly::UnusedChunks { first: i, count });
+        }
+        i += count.max(1);
+    }
+
+    if let Some(dim) = src_dim {
+        for (i, c) in lay.chunks.iter().enumerate() {
+            if let Err(SgSpriteErr::SrcOutOfBounds { x, y, .. }) = src_pos(lay, c, i, dim) {
+                res.push(Anomaly::ChunkOutOfSource { chunk: i, x, y });
+            }
+        }
+    }
+
+    res
+}
+
+/// Report sprites with no chunks on the `w x h` screen, chunk positions are relative to its center
+pub fn check_screen(lay: &ParsedLay, (w, h): (u32, u32)) -> Vec<Anomaly> {
+    let block = lay.block_size as i64;
+    let (half_w, half_h) = (w as i64 / 2, h as i64 / 2);
+    let on_screen = |c: &Chunk| {
+        let (x, y) = (c.img_x as i64, c.img_y as i64);
+        x + block > -half_w && x < half_w && y + block > -half_h && y < half_h
+    };
+
+    lay.sprites
+        .iter()
+        .enumerate()
+        .filter(|(i, s)| match sprite_chunks(lay, s, *i) {
+            Ok(chunks) => !chunks.is_empty() && !chunks.iter().any(on_screen),
+            Err(_) => false, // reported as BadChunkRange
+        })
+        .map(|(sprite, _)| Anomaly::OffScreen { sprite })
+        .collect()
+}
+
+/// Print reports to stdout
+pub fn print_reports(reports: &[LayReport]) {
+    for r in reports {
+        match (&r.error, r.anomalies.len()) {
+            (Some(e), _) => println!("{}: error: {}", r.path.display(), e),
+            (None, 0) => println!("{}: ok", r.path.display()),
+            (None, n) => println!("{}: {} anomalies", r.path.display(), n),
+        }
+
+        for a in &r.anomalies {
+            println!("  {}", a);
+        }
+    }
+}
+
+/// Print reports to stdout as JSON array
+#[cfg(feature = "json")]
+pub fn print_reports_json(reports: &[LayReport]) -> Result<(), SgSpriteErr> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    serde_json::to_writer_pretty(&mut out, reports).map_err(|e| SgSpriteErr::Io(e.into()))?;
+    println!();
+    Ok(())
+}
@@ -0,0 +1,428 @@
+//! CRI `.cpk` archive reader.
+//!
+//! Archive metadata is stored in `@UTF` tables: the header table right after the
+//! `"CPK "` chunk header at `0x0` and the file table after the `"TOC "` chunk header
+//! at `TocOffset`. Chunk headers are `[4:magic][u32:?][u64:packet_size]` (little-endian),
+//! tables themselves are big-endian and may be XOR-masked.
+//!
+//! Entries with `ExtractSize > FileSize` are compressed with CRILAYLA,
+//! see [`decompress_crilayla`].
+
+use super::*;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use std::io::{Read, Seek, SeekFrom};
+
+const CHUNK_HEADER_SZ: u64 = 0x10;
+const UTF_MAGIC: &[u8; 4] = b"@UTF";
+const UTF_HEADER_SZ: usize = 0x20;
+const UTF_BASE: usize = 8; // table offsets are relative to the end of [magic][size]
+
+const LAYLA_MAGIC: &[u8; 8] = b"CRILAYLA";
+const LAYLA_HEADER_SZ: usize = 0x10;
+const LAYLA_PREFIX_SZ: usize = 0x100; // stored uncompressed after compressed data
+const LAYLA_VLE_BITS: [u32; 4] = [2, 3, 5, 8];
+
+/// Archive entry
+#[derive(Clone, Debug)]
+pub struct CpkEntry {
+    /// `DirName/FileName`, or just `FileName` for root entries
+    pub name: String,
+    /// Absolute offset of the entry data
+    pub offset: u64,
+    /// Size of the stored data
+    pub size: u64,
+    pub size_uncompressed: u64,
+}
+
+/// Opened `.cpk` archive, entry data is read on demand
+pub struct CpkArchive<R> {
+    src: R,
+    entries: Vec<CpkEntry>,
+    /// Max size of decompressed entry
+    limit: u64,
+}
+
+impl<R: Read + Seek> CpkArchive<R> {
+    /// Read header and TOC tables
+    pub fn open(src: R) -> Result<Self, SgSpriteErr> {
+        CpkArchive::open_with(src, &Limits::default())
+    }
+
+    /// Same as [`CpkArchive::open`], compressed entries are decompressed up to `limits.decompressed`
+    pub fn open_with(mut src: R, limits: &Limits) -> Result<Self, SgSpriteErr> {
+        let file_len = src.seek(SeekFrom::End(0))?;
+
+        let header = UtfTable::parse(&read_chunk(&mut src, 0, b"CPK ", file_len)?)?;
+        let toc_offset = match header.uint(0, "TocOffset") {
+            Some(o) if o > 0 => o,
+            _ => raise!(bad_cpk("archive has no TOC")),
+        };
+
+        // file offsets are relative to either content or TOC, whichever comes first
+        let content_offset = header.uint(0, "ContentOffset").unwrap_or(toc_offset);
+        let base = content_offset.min(toc_offset);
+
+        let toc = UtfTable::parse(&read_chunk(&mut src, toc_offset, b"TOC ", file_len)?)?;
+        debug!("cpk, {} entries, base {:#X}", toc.rows.len(), base);
+
+        let mut entries = Vec::with_capacity(toc.rows.len());
+        for row in 0..toc.rows.len() {
+            let file_name = toc.string(row, "FileName").ok_or_else(|| bad_cpk("TOC entry without FileName"))?;
+            let name = match toc.string(row, "DirName") {
+                Some(dir) if !dir.is_empty() => fmt!("{}/{}", dir, file_name),
+                _ => file_name.to_string(),
+            };
+
+            let size = toc.uint(row, "FileSize").unwrap_or(0);
+            let e = CpkEntry {
+                offset: toc.uint(row, "FileOffset").unwrap_or(0).saturating_add(base),
+                size,
+                size_uncompressed: toc.uint(row, "ExtractSize").unwrap_or(size),
+                name,
+            };
+
+            let in_file = e.offset.checked_add(e.size).map(|end| end <= file_len).unwrap_or(false);
+            if !in_file {
+                raise!(bad_cpk(fmt!("entry {} is out of file", e.name)));
+            }
+
+            entries.push(e);
+        }
+
+        Ok(CpkArchive { src, entries, limit: limits.decompressed })
+    }
+
+    pub fn entries(&self) -> &[CpkEntry] {
+        &self.entries
+    }
+}
+
+impl<R: Read + Seek> Archive for CpkArchive<R> {
+    fn entry_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn entry_name(&self, index: usize) -> &str {
+        &self.entries[index].name
+    }
+
+    fn read(&mut self, index: usize) -> Result<Vec<u8>, SgSpriteErr> {
+        let e = &self.entries[index];
+        self.src.seek(SeekFrom::Start(e.offset))?;
+
+        let mut buf = Vec::with_capacity(e.size as usize);
+        (&mut self.src).take(e.size).read_to_end(&mut buf)?;
+
+        if buf.starts_with(LAYLA_MAGIC) {
+            decompress_crilayla(&buf, self.limit).map_err(|err| bad_cpk(fmt!("entry {}: {}", e.name, err)))
+        } else {
+            Ok(buf)
+        }
+    }
+}
+
+fn bad_cpk(msg: impl Into<String>) -> SgSpriteErr {
+    SgSpriteErr::BadArchive(msg.into())
+}
+
+/// Read the packet following the chunk header at `offset`
+fn read_chunk<R: Read + Seek>(
+    src: &mut R,
+    offset: u64,
+    magic: &[u8; 4],
+    file_len: u64,
+) -> Result<Vec<u8>, SgSpriteErr> {
+    let name = String::from_utf8_lossy(magic);
+    let name = name.trim_end();
+
+    let mut head = [0u8; CHUNK_HEADER_SZ as usize];
+    src.seek(SeekFrom::Start(offset))?;
+    src.read_exact(&mut head).map_err(|_| bad_cpk(fmt!("truncated {} chunk at {:#X}", name, offset)))?;
+
+    if &head[..4] != magic {
+        raise!(bad_cpk(fmt!("no {} chunk at {:#X}", name, offset)));
+    }
+
+    let size = LittleEndian::read_u64(&head[8..]);
+    let in_file = offset
+        .checked_add(CHUNK_HEADER_SZ)
+        .and_then(|start| start.checked_add(size))
+        .map(|end| end <= file_len)
+        .unwrap_or(false);
+
+    if !in_file {
+        raise!(bad_cpk(fmt!("{} chunk at {:#X} is out of file", name, offset)));
+    }
+
+    let mut packet = vec![0u8; size as usize];
+    src.read_exact(&mut packet)?;
+    Ok(packet)
+}
+
+/// Cell value of `@UTF` table
+#[derive(Clone, Debug, PartialEq)]
+enum UtfValue {
+    /// Column without value
+    Zero,
+    Uint(u64),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Data(Vec<u8>),
+}
+
+/// `@UTF` table, values of constant columns are repeated in every row
+struct UtfTable {
+    columns: Vec<String>,
+    rows: Vec<Vec<UtfValue>>,
+}
+
+const UTF_STORAGE_MASK: u8 = 0xF0;
+const UTF_STORAGE_ZERO: u8 = 0x10;
+const UTF_STORAGE_CONST: u8 = 0x30;
+const UTF_STORAGE_ROW: u8 = 0x50;
+const UTF_STORAGE_CONST2: u8 = 0x70;
+const UTF_TYPE_MASK: u8 = 0x0F;
+
+impl UtfTable {
+    fn parse(packet: &[u8]) -> Result<Self, SgSpriteErr> {
+        let mut unmasked;
+        let mut packet = packet;
+        if !packet.starts_with(UTF_MAGIC) {
+            unmasked = packet.to_vec();
+            unmask_utf(&mut unmasked);
+            packet = &unmasked;
+        }
+
+        if !packet.starts_with(UTF_MAGIC) || packet.len() < UTF_HEADER_SZ {
+            raise!(bad_cpk("bad @UTF table"));
+        }
+
+        let h = &packet[4..];
+        let table_sz = BigEndian::read_u32(&h[0..]) as usize;
+        let rows_at = BigEndian::read_u32(&h[4..]) as usize;
+        let strings_at = BigEndian::read_u32(&h[8..]) as usize;
+        let data_at = BigEndian::read_u32(&h[12..]) as usize;
+        let column_count = BigEndian::read_u16(&h[20..]) as usize;
+        let row_sz = BigEndian::read_u16(&h[22..]) as usize;
+        let row_count = BigEndian::read_u32(&h[24..]) as usize;
+
+        let table = packet
+            .get(UTF_BASE..UTF_BASE.saturating_add(table_sz))
+            .ok_or_else(|| bad_cpk("@UTF table is out of packet"))?;
+
+        let area = |at: usize| table.get(at..).ok_or_else(|| bad_cpk("@UTF table area is out of table"));
+        let strings = area(strings_at)?;
+        let data = area(data_at)?;
+
+        // columns: [u8:flags][u32:name][value if constant]
+        let mut cols = &packet[UTF_HEADER_SZ..];
+        let mut columns = Vec::with_capacity(column_count.min(cols.len()));
+        let mut col_flags = Vec::with_capacity(column_count.min(cols.len()));
+        let mut consts = Vec::with_capacity(column_count.min(cols.len()));
+
+        for _ in 0..column_count {
+            let flags = cols.read_u8().map_err(utf_err)?;
+            let name = utf_string(strings, cols.read_u32::<BigEndian>().map_err(utf_err)?)?;
+
+            consts.push(match flags & UTF_STORAGE_MASK {
+                UTF_STORAGE_CONST | UTF_STORAGE_CONST2 => Some(utf_value(flags, &mut cols, strings, data)?),
+                UTF_STORAGE_ZERO => Some(UtfValue::Zero),
+                UTF_STORAGE_ROW => None,
+                s => raise!(bad_cpk(fmt!("unknown @UTF storage {:#04X} of column {}", s, name))),
+            });
+
+            columns.push(name);
+            col_flags.push(flags);
+        }
+
+        // empty rows would take no table space, so their count would be unbounded
+        if row_sz == 0 && row_count > 0 {
+            raise!(bad_cpk("@UTF rows have zero size"));
+        }
+
+        let rows_sz = row_count.saturating_mul(row_sz);
+        let mut row_data = rows_at
+            .checked_add(rows_sz)
+            .and_then(|end| table.get(rows_at..end))
+            .ok_or_else(|| bad_cpk("@UTF rows are out of table"))?;
+
+        let mut rows = Vec::with_capacity(row_count.min(table.len()));
+        for _ in 0..row_count {
+            let mut r = &row_data[..row_sz];
+            row_data = &row_data[row_sz..];
+
+            let mut row = Vec::with_capacity(column_count);
+            for (c, flags) in consts.iter().zip(&col_flags) {
+                row.push(match c {
+                    Some(v) => v.clone(),
+                    None => utf_value(*flags, &mut r, strings, data)?,
+                });
+            }
+
+            rows.push(row);
+        }
+
+        Ok(UtfTable { columns, rows })
+    }
+
+    fn get(&self, row: usize, column: &str) -> Option<&UtfValue> {
+        let c = self.columns.iter().position(|n| n == column)?;
+        self.rows.get(row).map(|r| &r[c])
+    }
+
+    fn uint(&self, row: usize, column: &str) -> Option<u64> {
+        match self.get(row, column)? {
+            UtfValue::Zero => Some(0),
+            UtfValue::Uint(v) => Some(*v),
+            UtfValue::Int(v) if *v >= 0 => Some(*v as u64),
+            _ => None,
+        }
+    }
+
+    fn string(&self, row: usize, column: &str) -> Option<&str> {
+        match self.get(row, column)? {
+            UtfValue::Zero => Some(""),
+            UtfValue::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+fn utf_err(_: io::Error) -> SgSpriteErr {
+    bad_cpk("truncated @UTF table")
+}
+
+/// Tables of some archives are XOR-masked with LCG output
+fn unmask_utf(data: &mut [u8]) {
+    let mut m: u32 = 0x655F;
+    for b in data {
+        *b ^= m as u8;
+        m = m.wrapping_mul(0x4115);
+    }
+}
+
+fn utf_string(strings: &[u8], offset: u32) -> Result<String, SgSpriteErr> {
+    let s = strings.get(offset as usize..).ok_or_else(|| bad_cpk("@UTF string is out of table"))?;
+    let len = s.iter().position(|b| *b == 0).unwrap_or(s.len());
+    Ok(String::from_utf8_lossy(&s[..len]).into_owned())
+}
+
+fn utf_value(flags: u8, src: &mut &[u8], strings: &[u8], data: &[u8]) -> Result<UtfValue, SgSpriteErr> {
+    let v = match flags & UTF_TYPE_MASK {
+        0x0 => UtfValue::Uint(src.read_u8().map_err(utf_err)? as u64),
+        0x1 => UtfValue::Int(src.read_i8().map_err(utf_err)? as i64),
+        0x2 => UtfValue::Uint(src.read_u16::<BigEndian>().map_err(utf_err)? as u64),
+        0x3 => UtfValue::Int(src.read_i16::<BigEndian>().map_err(utf_err)? as i64),
+        0x4 => UtfValue::Uint(src.read_u32::<BigEndian>().map_err(utf_err)? as u64),
+        0x5 => UtfValue::Int(src.read_i32::<BigEndian>().map_err(utf_err)? as i64),
+        0x6 => UtfValue::Uint(src.read_u64::<BigEndian>().map_err(utf_err)?),
+        0x7 => UtfValue::Int(src.read_i64::<BigEndian>().map_err(utf_err)?),
+        0x8 => UtfValue::Float(src.read_f32::<BigEndian>().map_err(utf_err)? as f64),
+        0x9 => UtfValue::Float(src.read_f64::<BigEndian>().map_err(utf_err)?),
+        0xA => UtfValue::Str(utf_string(strings, src.read_u32::<BigEndian>().map_err(utf_err)?)?),
+        0xB => {
+            let at = src.read_u32::<BigEndian>().map_err(utf_err)? as usize;
+            let len = src.read_u32::<BigEndian>().map_err(utf_err)? as usize;
+            let d = at.checked_add(len).and_then(|end| data.get(at..end));
+            UtfValue::Data(d.ok_or_else(|| bad_cpk("@UTF data is out of table"))?.to_vec())
+        }
+        t => raise!(bad_cpk(fmt!("unknown @UTF type {:#X}", t))),
+    };
+
+    Ok(v)
+}
+
+/// Bit reader going from the end of the data to its start, MSB first
+struct RevBits<'a> {
+    data: &'a [u8],
+    pool: u8,
+    left: u32,
+}
+
+impl RevBits<'_> {
+    fn take(&mut self, count: u32) -> Result<u32, SgSpriteErr> {
+        let mut res = 0u32;
+        let mut taken = 0;
+
+        while taken < count {
+            if self.left == 0 {
+                let (last, rest) = self.data.split_last().ok_or_else(|| bad_cpk("truncated CRILAYLA stream"))?;
+                self.pool = *last;
+                self.data = rest;
+                self.left = 8;
+            }
+
+            let n = self.left.min(count - taken);
+            res = res << n | (self.pool as u32 >> (self.left - n)) & ((1 << n) - 1);
+            self.left -= n;
+            taken += n;
+        }
+
+        Ok(res)
+    }
+}
+
+/// Decompress CRILAYLA data.
+///
+/// Layout: `["CRILAYLA"][u32:size][u32:prefix_offset][compressed][0x100:prefix]`.
+/// The prefix is the first 0x100 bytes of the output, the remaining `size` bytes are decoded
+/// from the end of the compressed data backwards and written from the end of the output.
+/// Output larger than `limit` bytes is an error
+pub fn decompress_crilayla(data: &[u8], limit: u64) -> Result<Vec<u8>, SgSpriteErr> {
+    if data.len() < LAYLA_HEADER_SZ || !data.starts_with(LAYLA_MAGIC) {
+        raise!(bad_cpk("no CRILAYLA header"));
+    }
+
+    let size = LittleEndian::read_u32(&data[8..]) as usize;
+    if (LAYLA_PREFIX_SZ + size) as u64 > limit {
+        raise!(bad_cpk(fmt!("CRILAYLA output of {} bytes exceeds the limit", LAYLA_PREFIX_SZ + size)));
+    }
+    let prefix_at = LAYLA_HEADER_SZ + LittleEndian::read_u32(&data[12..]) as usize;
+    let prefix = data
+        .get(prefix_at..prefix_at + LAYLA_PREFIX_SZ)
+        .ok_or_else(|| bad_cpk("truncated CRILAYLA data"))?;
+
+    let mut out = vec![0u8; LAYLA_PREFIX_SZ + size];
+    out[..LAYLA_PREFIX_SZ].copy_from_slice(prefix);
+
+    let mut bits = RevBits { data: &data[LAYLA_HEADER_SZ..prefix_at], pool: 0, left: 0 };
+    let mut pos = out.len(); // everything at pos.. is decoded
+
+    while pos > LAYLA_PREFIX_SZ {
+        if bits.take(1)? == 0 {
+            pos -= 1;
+            out[pos] = bits.take(8)? as u8;
+            continue;
+        }
+
+        let distance = bits.take(13)? as usize + 3;
+        let mut len = 3;
+        let mut vle_maxed = true;
+        for &n in &LAYLA_VLE_BITS {
+            let v = bits.take(n)? as usize;
+            len += v;
+            if v != (1 << n) - 1 {
+                vle_maxed = false;
+                break;
+            }
+        }
+
+        while vle_maxed {
+            let v = bits.take(8)? as usize;
+            len += v;
+            vle_maxed = v == 0xFF;
+        }
+
+        if len > pos - LAYLA_PREFIX_SZ || pos + distance > out.len() {
+            raise!(bad_cpk("bad CRILAYLA back reference"));
+        }
+
+        for _ in 0..len {
+            pos -= 1;
+            out[pos] = out[pos + distance];
+        }
+    }
+
+    Ok(out)
+}
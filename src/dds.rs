@@ -0,0 +1,144 @@
+//! DirectDraw Surface `.dds` texture decoder.
+//!
+//! Layout (little-endian):
+//!
+//! - `[4:"DDS "]`, then `0x7C` bytes header: `[size][flags][height][width][pitch][depth][mip_count][0x2C:reserved]`
+//!   `[0x20:pixel_format][caps][caps2][caps3][caps4][4:reserved]`
+//! - pixel format: `[size][flags][4:fourcc][bit_count][r_mask][g_mask][b_mask][a_mask]`
+//! - if fourcc is `DX10`, `0x14` bytes extended header: `[dxgi_format][dimension][misc][array_size][misc2]`
+//!
+//! Only the top mip level of the first surface is decoded. Supported formats are BC1-3 (DXT1-5), BC7
+//! and uncompressed 24/32 bit RGB(A).
+
+use super::*;
+use crate::bcn::{self, BlockFormat};
+use byteorder::{ByteOrder, LittleEndian};
+use image::RgbaImage;
+
+const MAGIC: &[u8; 4] = b"DDS ";
+const HEADER_SZ: usize = 0x80;
+const DX10_HEADER_SZ: usize = 0x14;
+
+const PF_ALPHA_PIXELS: u32 = 0x1;
+const PF_FOURCC: u32 = 0x4;
+const PF_RGB: u32 = 0x40;
+
+enum DdsFormat {
+    Blocks(&'static BlockFormat),
+    /// Bytes per pixel and R, G, B, A masks, zero alpha mask is opaque
+    Masked(usize, [u32; 4]),
+}
+
+fn bad(msg: impl Into<String>) -> SgSpriteErr {
+    SgSpriteErr::BadTexture(msg.into())
+}
+
+/// Data starts with the DDS magic
+pub fn is_dds(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Size of the top level surface
+pub fn dds_dimensions(data: &[u8]) -> Result<(u32, u32), SgSpriteErr> {
+    if !is_dds(data) {
+        raise!(bad("not a DDS file"));
+    }
+    if data.len() < HEADER_SZ {
+        raise!(bad("truncated DDS header"));
+    }
+    Ok((LittleEndian::read_u32(&data[16..]), LittleEndian::read_u32(&data[12..])))
+}
+
+/// Decode the top level surface of the DDS file
+pub fn decode_dds(data: &[u8]) -> Result<RgbaImage, SgSpriteErr> {
+    let (w, h) = dds_dimensions(data)?;
+    let (format, offset) = pixel_format(data)?;
+    let surface = &data[offset..];
+
+    match format {
+        DdsFormat::Blocks(f) => bcn::decode_image(f, surface, w, h),
+        DdsFormat::Masked(bytes, masks) => {
+            let need = (w as usize)
+                .checked_mul(h as usize)
+                .and_then(|n| n.checked_mul(bytes))
+                .ok_or_else(|| bad(fmt!("RGB image {}x{} is too large", w, h)))?;
+            let surface = surface
+                .get(..need)
+                .ok_or_else(|| bad(fmt!("RGB data is {} bytes, {} expected", surface.len(), need)))?;
+
+            let px = surface
+                .chunks_exact(bytes)
+                .flat_map(|p| {
+                    let v = LittleEndian::read_uint(p, bytes) as u32;
+                    let mut res = [0xFF; 4];
+                    for (c, mask) in res.iter_mut().zip(&masks) {
+                        if *mask != 0 {
+                            *c = unmask(v, *mask);
+                        }
+                    }
+                    res
+                })
+                .collect();
+            Ok(RgbaImage::from_raw(w, h, px).expect("pixel buffer size"))
+        }
+    }
+}
+
+/// Masked channel value scaled to 8 bits
+fn unmask(v: u32, mask: u32) -> u8 {
+    let max = mask >> mask.trailing_zeros();
+    let c = (v & mask) >> mask.trailing_zeros();
+    (c as u64 * 0xFF / max as u64) as u8
+}
+
+/// Surface format and data offset
+fn pixel_format(data: &[u8]) -> Result<(DdsFormat, usize), SgSpriteErr> {
+    let pf = &data[0x4C..0x6C];
+    let flags = LittleEndian::read_u32(&pf[4..]);
+    let fourcc = &pf[8..12];
+
+    if flags & PF_FOURCC != 0 {
+        let format = match fourcc {
+            b"DXT1" => &bcn::BC1,
+            b"DXT2" | b"DXT3" => &bcn::BC2,
+            b"DXT4" | b"DXT5" => &bcn::BC3,
+            b"DX10" => return dx10_format(data),
+            _ => raise!(bad(fmt!("unsupported DDS format {}", String::from_utf8_lossy(fourcc)))),
+        };
+        return Ok((DdsFormat::Blocks(format), HEADER_SZ));
+    }
+
+    let bit_count = LittleEndian::read_u32(&pf[12..]);
+    if flags & PF_RGB == 0 || !(bit_count == 24 || bit_count == 32) {
+        raise!(bad(fmt!("unsupported DDS pixel format (flags {:#X}, {} bits)", flags, bit_count)));
+    }
+
+    let mut masks = [0u32; 4];
+    for (i, m) in masks.iter_mut().enumerate() {
+        *m = LittleEndian::read_u32(&pf[16 + i * 4..]);
+    }
+    if flags & PF_ALPHA_PIXELS == 0 {
+        masks[3] = 0;
+    }
+    if masks[..3].contains(&0) {
+        raise!(bad("DDS color mask is empty"));
+    }
+
+    Ok((DdsFormat::Masked(bit_count as usize / 8, masks), HEADER_SZ))
+}
+
+fn dx10_format(data: &[u8]) -> Result<(DdsFormat, usize), SgSpriteErr> {
+    let dx10 = data.get(HEADER_SZ..HEADER_SZ + DX10_HEADER_SZ).ok_or_else(|| bad("truncated DX10 header"))?;
+
+    let format = match LittleEndian::read_u32(dx10) {
+        71 | 72 => DdsFormat::Blocks(&bcn::BC1),
+        74 | 75 => DdsFormat::Blocks(&bcn::BC2),
+        77 | 78 => DdsFormat::Blocks(&bcn::BC3),
+        98 | 99 => DdsFormat::Blocks(&bcn::BC7),
+        28 | 29 => DdsFormat::Masked(4, [0xFF, 0xFF00, 0xFF_0000, 0xFF00_0000]),
+        87 | 91 => DdsFormat::Masked(4, [0xFF_0000, 0xFF00, 0xFF, 0xFF00_0000]),
+        f => raise!(bad(fmt!("unsupported DXGI format {}", f))),
+    };
+
+    Ok((format, HEADER_SZ + DX10_HEADER_SZ))
+}
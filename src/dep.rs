@@ -1,23 +1,30 @@
+//! Sprite dependency resolution.
+
 use crate::parse::{ParsedLay, Sprite, SpriteT};
-use crate::raise;
 use crate::SgSpriteErr;
 
+/// Sprite with its resolved dependency
 pub struct DepNode<'a> {
     pub sprite: &'a Sprite,
+    /// Index of the sprite in [`ParsedLay::sprites`]
+    pub index: usize,
+    /// Number of sprites depending on this one
     pub ref_count: usize,
+    /// Index of the node this sprite is drawn over
     pub depends_on: Option<usize>,
 }
 
+/// Dependency graph of all sprites in the lay, indexed the same way as [`ParsedLay::sprites`]
 pub struct DepGraph<'a>(Vec<DepNode<'a>>);
 
-fn node(s: &Sprite) -> DepNode {
-    DepNode { sprite: s, ref_count: 0, depends_on: None }
+fn node((index, s): (usize, &Sprite)) -> DepNode<'_> {
+    DepNode { sprite: s, index, ref_count: 0, depends_on: None }
 }
 
 impl<'g> DepGraph<'g> {
-    pub fn resolve_dep_graph(lay: &ParsedLay) -> DepGraph {
+    pub fn resolve_dep_graph(lay: &ParsedLay) -> DepGraph<'_> {
         let base_dep = lay.base_dep;
-        let mut node_list: Vec<_> = lay.sprites.iter().map(node).collect();
+        let mut node_list: Vec<_> = lay.sprites.iter().enumerate().map(node).collect();
 
         // Dep: depend on Base/first sprite
         //      implicitly if parent is absent
@@ -25,7 +32,7 @@ impl<'g> DepGraph<'g> {
         for i in 0..node_list.len() {
             let n = &mut node_list[i]; // No way to modify other nodes while iterator is borrowed
             let dep_idx = match n.sprite.sprite_type {
-                SpriteT::Base | SpriteT::Overlay => continue,
+                SpriteT::Base | SpriteT::Overlay | SpriteT::Unknown(_) => continue,
                 SpriteT::Sub => base_dep,
                 SpriteT::Dep { depends_on: dep, .. } => match lay.sub_map.get(&dep) {
                     Some(d) => Some(*d),
@@ -42,24 +49,26 @@ impl<'g> DepGraph<'g> {
         DepGraph(node_list)
     }
 
+    /// Sprites nothing depends on, each of them is a separate variant to draw
     pub fn get_leaf_sprites<'a>(&'a self) -> impl Iterator<Item = &'a DepNode<'g>> {
         self.0.iter().filter(|d| d.ref_count == 0)
     }
 
-    pub fn resolve_layers(&self, leaf: &'g DepNode) -> Result<Vec<&'g Sprite>, SgSpriteErr> {
+    /// Layers to draw for the given leaf, from top to bottom
+    pub fn resolve_layers<'a>(&'a self, leaf: &'a DepNode<'g>) -> Result<Vec<&'a DepNode<'g>>, SgSpriteErr> {
         if leaf.sprite.sprite_type == SpriteT::Overlay {
-            return Ok(vec![leaf.sprite]); // draw only overlay itself
+            return Ok(vec![leaf]); // draw only overlay itself
         }
 
         let node_count = self.0.len();
-        let mut layers: Vec<&Sprite> = Vec::with_capacity(3);
+        let mut layers: Vec<&DepNode> = Vec::with_capacity(3);
 
         let mut next = Some(leaf);
         while let Some(s) = next {
-            layers.push(s.sprite);
+            layers.push(s);
             next = s.depends_on.map(|d| &self.0[d]);
             if layers.len() > node_count {
-                raise!("sprite layer list resolve looped");
+                raise!(SgSpriteErr::LayerLoop);
             }
         }
 
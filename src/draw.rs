@@ -1,42 +1,88 @@
+//! Sprite composition from the source png.
+
 use super::*;
+use crate::bntx::{bntx_dimensions, decode_bntx, is_bntx};
+use crate::dds::{decode_dds, dds_dimensions, is_dds};
+use crate::gxt::{decode_gxt, gxt_dimensions, is_gxt};
+use crate::validate::{dst_pos, sprite_chunks, src_pos};
 use image::{
     self, imageops, DynamicImage, GenericImage, GenericImageView, ImageBuffer, Pixels, Rgba,
     RgbaImage,
 };
+use log::{debug, trace};
 use std::format as fmt;
 use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::Path;
+use std::io::{BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
 
-pub const BLOCK_W: u32 = 32;
-pub const BLOCK_H: u32 = 32;
 pub const CANVAS_PAD_W: u32 = 0;
 pub const CANVAS_PAD_H: u32 = 0;
 
+/// Decoded source image
 pub struct DrawPrep {
     img: DynamicImage,
 }
 
+impl DrawPrep {
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.img.dimensions()
+    }
+}
+
+/// Open and decode the source image: png, GXT, DDS or BNTX
 pub fn draw_prep(img: &Path) -> Result<DrawPrep, SgSpriteErr> {
-    eprint!("draw: decode");
-    Ok(DrawPrep { img: image::open(img)? })
+    debug!("draw: decode {}", img.display());
+    decode_source(&std::fs::read(img)?)
+}
+
+/// Decode the source image from memory
+pub fn draw_prep_bytes(img: &[u8]) -> Result<DrawPrep, SgSpriteErr> {
+    debug!("draw: decode {} bytes", img.len());
+    decode_source(img)
 }
 
+fn decode_source(data: &[u8]) -> Result<DrawPrep, SgSpriteErr> {
+    let img = if is_gxt(data) {
+        DynamicImage::ImageRgba8(decode_gxt(data)?)
+    } else if is_dds(data) {
+        DynamicImage::ImageRgba8(decode_dds(data)?)
+    } else if is_bntx(data) {
+        DynamicImage::ImageRgba8(decode_bntx(data)?)
+    } else {
+        image::load_from_memory(data)?
+    };
+    Ok(DrawPrep { img })
+}
+
+/// Size of the encoded source image, without decoding the pixels
+pub fn source_dimensions(data: &[u8]) -> Result<(u32, u32), SgSpriteErr> {
+    if is_gxt(data) {
+        return gxt_dimensions(data);
+    }
+    if is_dds(data) {
+        return dds_dimensions(data);
+    }
+    if is_bntx(data) {
+        return bntx_dimensions(data);
+    }
+    let reader = image::io::Reader::new(Cursor::new(data)).with_guessed_format()?;
+    Ok(reader.into_dimensions()?)
+}
+
+/// Compose `sprites` (as returned by [`DepGraph::resolve_layers`]) into a new image.
+/// Chunks out of the source image or the canvas are reported as errors
 pub fn draw_sprites(
     src: &mut DrawPrep,
-    png_out: impl AsRef<Path>,
-    sprites: &[&Sprite],
+    sprites: &[&DepNode],
     pass: usize,
     lay: &ParsedLay,
-) -> Result<(), SgSpriteErr> {
+) -> Result<RgbaImage, SgSpriteErr> {
     macro_rules! status { ($($e:tt)*) => {
-    eprint!("\rdraw {:02}: {}", pass, format_args!($($e)*));
+    trace!("draw {:02}: {}", pass, format_args!($($e)*));
   }}
     status!("");
 
-    let (x_mid, y_mid) = lay.sprite_xy_min;
-    let x_mid = x_mid.abs();
-    let y_mid = y_mid.abs();
+    let src_dim = src.img.dimensions();
 
     status!("canvas");
     let mut canvas = ImageBuffer::from_pixel(
@@ -47,25 +93,53 @@ pub fn draw_sprites(
 
     let mut chunk_count = 0usize;
 
-    for s in sprites.iter().rev() {
-        let chunks = &lay.chunks[s.chunk_offset..][..s.chunk_count];
+    for node in sprites.iter().rev() {
+        let s = node.sprite;
+        let chunks = sprite_chunks(lay, s, node.index)?;
 
-        for c in chunks.iter() {
+        for (i, c) in chunks.iter().enumerate() {
             status!("{} chunks", chunk_count + 1);
 
-            let (cx, cy) = ((x_mid + c.img_x) as u32, (y_mid + c.img_y) as u32);
-            let (sx, sy) = (c.chunk_x as u32 - 1, c.chunk_y as u32 - 1);
-            let mut dst_chunk = imageops::crop(&mut canvas, cx, cy, BLOCK_W, BLOCK_H);
-            let src_chunk = imageops::crop(&mut src.img, sx, sy, BLOCK_W, BLOCK_H);
+            let chunk_idx = s.chunk_offset + i;
+            let (cx, cy) = dst_pos(lay, c, chunk_idx)?;
+            let (sx, sy) = src_pos(lay, c, chunk_idx, src_dim)?;
+            let mut dst_chunk = imageops::crop(&mut canvas, cx, cy, lay.block_size, lay.block_size);
+            let src_chunk = imageops::crop(&mut src.img, sx, sy, lay.block_size, lay.block_size);
 
             imageops::replace(&mut dst_chunk, &src_chunk, 0, 0);
             chunk_count += 1;
         }
     }
 
-    eprint!(", encoding: ");
-    canvas.save(png_out)?;
+    debug!("draw {:02}: {} chunks", pass, chunk_count);
+    Ok(canvas)
+}
+
+/// Destination for composed sprites
+pub trait SpriteSink {
+    /// `name` is the variant name without extension, e.g. `CRS_A_s2`
+    fn put(&mut self, name: &str, sprite: RgbaImage) -> Result<(), SgSpriteErr>;
+}
+
+/// Saves sprites as `<name>.png` into the directory
+pub struct DirSink(pub PathBuf);
+
+impl SpriteSink for DirSink {
+    fn put(&mut self, name: &str, sprite: RgbaImage) -> Result<(), SgSpriteErr> {
+        save_png(&sprite, self.0.join(fmt!("{}.png", name)))
+    }
+}
+
+impl<F: FnMut(&str, RgbaImage) -> Result<(), SgSpriteErr>> SpriteSink for F {
+    fn put(&mut self, name: &str, sprite: RgbaImage) -> Result<(), SgSpriteErr> {
+        self(name, sprite)
+    }
+}
 
-    eprintln!("done");
+/// Encode composed sprite as png
+pub fn save_png(sprite: &RgbaImage, png_out: impl AsRef<Path>) -> Result<(), SgSpriteErr> {
+    let png_out = png_out.as_ref();
+    debug!("encoding {}", png_out.display());
+    sprite.save(png_out)?;
     Ok(())
 }
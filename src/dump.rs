@@ -0,0 +1,101 @@
+//! Annotated hex dump of lay files, used by `--dump` mode.
+
+use super::*;
+use crate::parse::{detect_endian, CHUNK_SZ, HEADER_SZ, SPRITE_SZ};
+use std::io::Write;
+
+const TRAILING_ROW: usize = 16;
+
+struct Dumper<'a, W> {
+    data: &'a [u8],
+    pos: usize,
+    endian: Endian,
+    out: W,
+}
+
+impl<'a, W: Write> Dumper<'a, W> {
+    /// Print next `len` bytes along with their description, None if there's not enough data
+    fn field(&mut self, len: usize, desc: impl FnOnce(&[u8]) -> String) -> io::Result<Option<&'a [u8]>> {
+        let bytes = match self.data.get(self.pos..self.pos + len) {
+            Some(b) => b,
+            None => {
+                let left = self.data.len() - self.pos;
+                writeln!(self.out, "{:08X}  -- truncated, {} bytes left", self.pos, left)?;
+                return Ok(None);
+            }
+        };
+
+        let hex = fmt!("{:X}", Hex(bytes));
+        writeln!(self.out, "{:08X}  {:<32}  {}", self.pos, hex, desc(bytes))?;
+        self.pos += len;
+        Ok(Some(bytes))
+    }
+
+    fn u32(&mut self, desc: &str) -> io::Result<Option<u32>> {
+        let e = self.endian;
+        let b = self.field(4, |b| fmt!("{} = {}", desc, e.read_u32(b)))?;
+        Ok(b.map(|b| e.read_u32(b)))
+    }
+
+    fn f32(&mut self, desc: &str) -> io::Result<Option<f32>> {
+        let e = self.endian;
+        let b = self.field(4, |b| fmt!("{} = {}", desc, e.read_f32(b)))?;
+        Ok(b.map(|b| e.read_f32(b)))
+    }
+
+    fn section(&mut self, name: impl Display) -> io::Result<()> {
+        writeln!(self.out, "{:8}  -- {}", "", name)
+    }
+}
+
+/// Print annotated hex dump of the (decompressed) lay file. Compression, byte order
+/// and size limit are taken from `opts`, other options don't apply
+pub fn dump_lay(data: &[u8], opts: &ParseOpts, out: impl Write) -> Result<(), SgSpriteErr> {
+    let (data, compression) = opts.decompress_lay(data)?;
+    let endian = opts.endian.or_else(|| opts.detect_endian(&data)).unwrap_or_default();
+    let mut d = Dumper { data: &data, pos: 0, endian, out };
+
+    writeln!(d.out, "# {:?} lay, {} bytes decompressed, {:?} endian", compression, data.len(), endian)?;
+    dump_impl(&mut d)?;
+    Ok(())
+}
+
+fn dump_impl<W: Write>(d: &mut Dumper<W>) -> io::Result<()> {
+    macro_rules! next { ($e:expr) => { match $e? { Some(v) => v, None => return Ok(()) } } }
+
+    d.section(format_args!("header ({} bytes)", HEADER_SZ))?;
+    let sprite_count = next!(d.u32("sprite_count"));
+    let chunk_count = next!(d.u32("chunk_count"));
+
+    d.section(format_args!("sprite list ({} bytes per entry)", SPRITE_SZ))?;
+    for i in 0..sprite_count {
+        let e = d.endian;
+        next!(d.field(4, |b| {
+            // info is a u32 with A in the lowest byte
+            let b = e.read_u32(b).to_le_bytes();
+            let t = SpriteT::from_info(b);
+            fmt!("sprite {}: A(id)={:#04X} B={:#04X} C={:#04X} D={:#04X} {:?}", i, b[0], b[1], b[2], b[3], t)
+        }));
+        next!(d.u32("  chunk_offset"));
+        next!(d.u32("  chunk_count"));
+    }
+
+    d.section(format_args!("chunk list ({} bytes per entry)", CHUNK_SZ))?;
+    for i in 0..chunk_count {
+        next!(d.f32(&fmt!("chunk {}: dst_x", i)));
+        next!(d.f32("  dst_y"));
+        next!(d.f32("  src_x"));
+        next!(d.f32("  src_y"));
+    }
+
+    let left = d.data.len() - d.pos;
+    if left > 0 {
+        d.section(format_args!("trailing data ({} bytes)", left))?;
+        while d.pos < d.data.len() {
+            let len = TRAILING_ROW.min(d.data.len() - d.pos);
+            next!(d.field(len, |_| String::new()));
+        }
+    }
+
+    Ok(())
+}
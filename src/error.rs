@@ -0,0 +1,162 @@
+//! Library error type.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+macro_rules! raise {
+    ($e:expr) => { return Err($e.into()) };
+}
+
+/// Part of the lay file an error is related to
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum LaySection {
+    Header,
+    Sprites,
+    Chunks,
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SgSpriteErr {
+    /// I/O error outside of lay parsing (opening files, reading dirs, etc)
+    Io(io::Error),
+    /// Source image decode or sprite encode error
+    Image(image::ImageError),
+    /// Header can't be read or decompression can't be started
+    BadHeader(io::Error),
+    /// File is neither raw lay nor any of the supported compressed streams
+    UnknownCompression,
+    /// File ended in the middle of the section
+    Truncated { section: LaySection, offset: u64 },
+    /// Unknown sprite type byte (`D`)
+    UnknownSpriteType { type_id: u8, offset: u64 },
+    /// Chunk coordinate is not an integer
+    BadCoord { value: f32, offset: u64 },
+    NoSprites,
+    /// Sprite dependencies form a loop
+    LayerLoop,
+    /// Sprite chunk range exceeds the chunk list
+    ChunkRange { sprite: usize, offset: usize, count: usize, total: usize },
+    /// Chunk block at `x, y` doesn't fit into the `w x h` source image
+    SrcOutOfBounds { chunk: usize, x: i64, y: i64, w: u32, h: u32 },
+    /// Chunk block at `x, y` doesn't fit into the `w x h` canvas
+    DstOutOfBounds { chunk: usize, x: i64, y: i64, w: u32, h: u32 },
+    /// File name doesn't have lay extension
+    NotLayFile,
+    /// No png found for the given sprite name
+    NoSourcePng(String),
+    /// Archive is malformed or has unsupported version
+    BadArchive(String),
+    /// Texture is malformed or has unsupported format
+    BadTexture(String),
+    /// Pairing regex or manifest is invalid
+    BadPairing(String),
+    /// Header sprite count exceeds the parser limit
+    TooManySprites { count: u32, limit: u32 },
+    /// Header chunk count exceeds the parser limit
+    TooManyChunks { count: u32, limit: u32 },
+    /// Decompressed lay exceeds the parser size limit
+    TooLarge { limit: u64 },
+    /// Sprite canvas side exceeds the parser limit
+    CanvasTooLarge { w: i64, h: i64, limit: u32 },
+    /// Count or chunk index doesn't fit into its u32 field of the written lay
+    FieldOverflow { field: &'static str, value: usize },
+    /// Invalid options
+    Usage(&'static str),
+}
+
+impl SgSpriteErr {
+    /// Byte offset of the failed entry (in decompressed data)
+    pub fn offset(&self) -> Option<u64> {
+        use SgSpriteErr::*;
+        match *self {
+            Truncated { offset, .. } | UnknownSpriteType { offset, .. } | BadCoord { offset, .. } => {
+                Some(offset)
+            }
+            BadHeader(_) | UnknownCompression | TooManySprites { .. } | TooManyChunks { .. } => Some(0),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn read(e: io::Error, section: LaySection, offset: u64) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => SgSpriteErr::Truncated { section, offset },
+            _ if section == LaySection::Header => SgSpriteErr::BadHeader(e),
+            _ => SgSpriteErr::Io(e),
+        }
+    }
+}
+
+impl Display for LaySection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LaySection::Header => "header",
+            LaySection::Sprites => "sprite list",
+            LaySection::Chunks => "chunk list",
+        })
+    }
+}
+
+impl Display for SgSpriteErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use SgSpriteErr::*;
+        match self {
+            Io(e) => write!(f, "{}", e),
+            Image(e) => write!(f, "image: {}", e),
+            BadHeader(e) => write!(f, "bad lay header: {}", e),
+            UnknownCompression => f.write_str("not a lay file or unknown compression"),
+            Truncated { section, offset } => write!(f, "truncated {} at {:#X}", section, offset),
+            UnknownSpriteType { type_id, offset } => {
+                write!(f, "Unknown sprite type {:#04X} at {:#X}", type_id, offset)
+            }
+            BadCoord { value, offset } => write!(f, "unsuitable chunk coord {} at {:#X}", value, offset),
+            NoSprites => f.write_str("no sprites"),
+            LayerLoop => f.write_str("sprite layer list resolve looped"),
+            ChunkRange { sprite, offset, count, total } => write!(
+                f,
+                "sprite {} chunks {}+{} are out of chunk list ({} chunks)",
+                sprite, offset, count, total
+            ),
+            SrcOutOfBounds { chunk, x, y, w, h } => {
+                write!(f, "chunk {} at {},{} is out of source image {}x{}", chunk, x, y, w, h)
+            }
+            DstOutOfBounds { chunk, x, y, w, h } => {
+                write!(f, "chunk {} at {},{} is out of canvas {}x{}", chunk, x, y, w, h)
+            }
+            NotLayFile => f.write_str("not a lay file"),
+            NoSourcePng(name) => write!(f, "No corresponding png file for {}", name),
+            BadArchive(msg) => write!(f, "bad archive: {}", msg),
+            BadTexture(msg) => write!(f, "bad texture: {}", msg),
+            BadPairing(msg) => write!(f, "bad pairing: {}", msg),
+            TooManySprites { count, limit } => write!(f, "{} sprites exceed the limit of {}", count, limit),
+            TooManyChunks { count, limit } => write!(f, "{} chunks exceed the limit of {}", count, limit),
+            TooLarge { limit } => write!(f, "decompressed lay exceeds the limit of {} bytes", limit),
+            CanvasTooLarge { w, h, limit } => write!(f, "canvas {}x{} exceeds the limit of {}x{}", w, h, limit, limit),
+            FieldOverflow { field, value } => write!(f, "{} {} doesn't fit into lay file", field, value),
+            Usage(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl Error for SgSpriteErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SgSpriteErr::Io(e) | SgSpriteErr::BadHeader(e) => Some(e),
+            SgSpriteErr::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SgSpriteErr {
+    fn from(e: io::Error) -> Self {
+        SgSpriteErr::Io(e)
+    }
+}
+
+impl From<image::ImageError> for SgSpriteErr {
+    fn from(e: image::ImageError) -> Self {
+        SgSpriteErr::Image(e)
+    }
+}
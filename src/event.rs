@@ -0,0 +1,57 @@
+//! Progress events reported while processing lay files.
+
+use super::*;
+
+/// Event emitted by [`lib_main_with`]
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// Lay file processing started, `index` is zero-based
+    LayStarted { index: usize, total: usize, path: &'a Path, name: &'a str },
+    /// Source image found for the current lay
+    SourceChosen { path: &'a Path },
+    /// Variant `n` (one-based) out of `of` was composed and passed to the sink
+    VariantDrawn { name: &'a str, n: usize, of: usize },
+    /// Variant can't be drawn, remaining variants are still processed
+    VariantFailed { name: &'a str, error: &'a SgSpriteErr },
+    /// Variants limit reached, the rest is skipped
+    LimitReached { limit: usize },
+    /// Format anomaly found while parsing
+    Warning(&'a LayWarning),
+    /// Lay file processing finished with the count of drawn variants or an error
+    LayFinished { path: &'a Path, result: Result<usize, &'a SgSpriteErr> },
+}
+
+/// Receiver of progress events
+pub trait Observer {
+    fn event(&mut self, e: &Event<'_>);
+}
+
+impl<F: FnMut(&Event<'_>)> Observer for F {
+    fn event(&mut self, e: &Event<'_>) {
+        self(e)
+    }
+}
+
+/// Reports events through `log`, used by the binary
+pub struct LogObserver;
+
+impl Observer for LogObserver {
+    fn event(&mut self, e: &Event<'_>) {
+        match e {
+            Event::LayStarted { index, total, name, .. } => info!("[{}/{}] {}", index + 1, total, name),
+            Event::SourceChosen { path } => info!(
+                "Using source file: {}",
+                path.file_name().and_then(|n| n.to_str()).unwrap_or("???")
+            ),
+            Event::VariantDrawn { name, n, of } => debug!("drawn {}/{}: {}", n, of, name),
+            Event::VariantFailed { name, error } => error!("{}: {}", name, error),
+            Event::LimitReached { .. } => info!("Limit reached, proceeding to next sprite"),
+            Event::Warning(w) => warn!("{}", w),
+            Event::LayFinished { result: Ok(_), .. } => (),
+            Event::LayFinished { path, result: Err(e) } => {
+                print_err(e);
+                print_err(format_args!("({})", path.display()));
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! PS Vita `.gxt` texture decoder.
+//!
+//! Layout (little-endian, version 3 only):
+//!
+//! - header, `0x20` bytes: `[4:"GXT\0"][version][texture_count][data_offset][data_size]`
+//!   `[p4_palette_count][p8_palette_count][4:padding]`
+//! - texture table, `0x20` bytes per texture:
+//!   `[offset][size][i32:palette][flags][type][format][u16:width][u16:height][u16:mip_count][2:padding]`
+//!
+//! Only the first texture is decoded. Supported formats are `U8U8U8U8` (any component order),
+//! BC1-3 (DXT1-5) and PVRTC 2/4 bpp, swizzled or linear.
+
+use super::*;
+use crate::bcn::{self, BlockFormat};
+use crate::pvrtc;
+use crate::swizzle::unswizzle_morton;
+use byteorder::{ByteOrder, LittleEndian};
+use image::RgbaImage;
+
+const MAGIC: &[u8; 4] = b"GXT\0";
+const VERSION_3: u32 = 0x1000_0003;
+const HEADER_SZ: usize = 0x20;
+const TEXTURE_SZ: usize = 0x20;
+
+const TYPE_SWIZZLED: u32 = 0x0000_0000;
+const TYPE_LINEAR: u32 = 0x6000_0000;
+const TYPE_SWIZZLED_ARBITRARY: u32 = 0xA000_0000;
+
+const BASE_FORMAT_MASK: u32 = 0x9F00_0000;
+const SWIZZLE_MASK: u32 = 0x0000_F000;
+
+const FORMAT_U8U8U8U8: u32 = 0x0C00_0000;
+const FORMAT_PVRT2BPP: u32 = 0x8000_0000;
+const FORMAT_PVRT4BPP: u32 = 0x8100_0000;
+const FORMAT_UBC1: u32 = 0x8500_0000;
+const FORMAT_UBC2: u32 = 0x8600_0000;
+const FORMAT_UBC3: u32 = 0x8700_0000;
+
+/// Texture table entry
+struct TextureInfo {
+    offset: usize,
+    size: usize,
+    ttype: u32,
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+fn bad(msg: impl Into<String>) -> SgSpriteErr {
+    SgSpriteErr::BadTexture(msg.into())
+}
+
+/// Data starts with the GXT magic
+pub fn is_gxt(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Size of the first texture
+pub fn gxt_dimensions(data: &[u8]) -> Result<(u32, u32), SgSpriteErr> {
+    let tex = first_texture(data)?;
+    Ok((tex.width, tex.height))
+}
+
+/// Decode the first texture of the GXT file
+pub fn decode_gxt(data: &[u8]) -> Result<RgbaImage, SgSpriteErr> {
+    let tex = first_texture(data)?;
+    let (w, h) = (tex.width, tex.height);
+    let tex_data = data
+        .get(tex.offset..tex.offset.saturating_add(tex.size))
+        .ok_or_else(|| bad("texture data is out of file"))?;
+
+    let swizzled = match tex.ttype {
+        TYPE_SWIZZLED | TYPE_SWIZZLED_ARBITRARY => true,
+        TYPE_LINEAR => false,
+        t => raise!(bad(fmt!("unsupported GXT texture type {:#010X}", t))),
+    };
+
+    let block_format = |f: &BlockFormat| -> Result<RgbaImage, SgSpriteErr> {
+        if swizzled {
+            let blocks = unswizzle_morton(tex_data, bcn::blocks(w), bcn::blocks(h), f.block_sz)
+                .ok_or_else(|| bad(fmt!("swizzled {} data is too short", f.name)))?;
+            bcn::decode_image(f, &blocks, w, h)
+        } else {
+            bcn::decode_image(f, tex_data, w, h)
+        }
+    };
+
+    match tex.format & BASE_FORMAT_MASK {
+        FORMAT_U8U8U8U8 => {
+            let (w_px, h_px) = (w as usize, h as usize);
+            let px = if swizzled {
+                unswizzle_morton(tex_data, w_px, h_px, 4).ok_or_else(|| bad("swizzled U8U8U8U8 data is too short"))?
+            } else {
+                tex_data.get(..w_px * h_px * 4).ok_or_else(|| bad("U8U8U8U8 data is too short"))?.to_vec()
+            };
+            let px = rgba_order(px, tex.format & SWIZZLE_MASK)?;
+            Ok(RgbaImage::from_raw(w, h, px).expect("pixel buffer size"))
+        }
+        FORMAT_UBC1 => block_format(&bcn::BC1),
+        FORMAT_UBC2 => block_format(&bcn::BC2),
+        FORMAT_UBC3 => block_format(&bcn::BC3),
+        // PVRTC blocks are always twiddled
+        FORMAT_PVRT2BPP => pvrtc::decode_pvrtc(tex_data, w, h, true),
+        FORMAT_PVRT4BPP => pvrtc::decode_pvrtc(tex_data, w, h, false),
+        f => raise!(bad(fmt!("unsupported GXT texture format {:#010X}", f))),
+    }
+}
+
+/// Reorder U8U8U8U8 pixels into RGBA. Swizzle names list components from the most
+/// significant byte, so `ABGR` is stored as `R, G, B, A`
+fn rgba_order(mut px: Vec<u8>, swizzle: u32) -> Result<Vec<u8>, SgSpriteErr> {
+    // source byte index of R, G, B, A; None for constant 0xFF alpha
+    let (order, alpha): ([usize; 3], Option<usize>) = match swizzle {
+        0x0000 => ([0, 1, 2], Some(3)), // ABGR
+        0x1000 => ([2, 1, 0], Some(3)), // ARGB
+        0x2000 => ([3, 2, 1], Some(0)), // RGBA
+        0x3000 => ([1, 2, 3], Some(0)), // BGRA
+        0x4000 => ([0, 1, 2], None),    // 1BGR
+        0x5000 => ([2, 1, 0], None),    // 1RGB
+        0x6000 => ([3, 2, 1], None),    // RGB1
+        0x7000 => ([1, 2, 3], None),    // BGR1
+        s => raise!(bad(fmt!("unsupported U8U8U8U8 swizzle {:#06X}", s))),
+    };
+
+    for p in px.chunks_exact_mut(4) {
+        let src = [p[0], p[1], p[2], p[3]];
+        p[0] = src[order[0]];
+        p[1] = src[order[1]];
+        p[2] = src[order[2]];
+        p[3] = alpha.map(|a| src[a]).unwrap_or(0xFF);
+    }
+
+    Ok(px)
+}
+
+fn first_texture(data: &[u8]) -> Result<TextureInfo, SgSpriteErr> {
+    if !is_gxt(data) {
+        raise!(bad("not a GXT file"));
+    }
+    if data.len() < HEADER_SZ + TEXTURE_SZ {
+        raise!(bad("truncated GXT header"));
+    }
+
+    let version = LittleEndian::read_u32(&data[4..]);
+    if version != VERSION_3 {
+        raise!(bad(fmt!("unsupported GXT version {:#010X}", version)));
+    }
+    if LittleEndian::read_u32(&data[8..]) == 0 {
+        raise!(bad("GXT has no textures"));
+    }
+
+    let t = &data[HEADER_SZ..HEADER_SZ + TEXTURE_SZ];
+    Ok(TextureInfo {
+        offset: LittleEndian::read_u32(&t[0..]) as usize,
+        size: LittleEndian::read_u32(&t[4..]) as usize,
+        ttype: LittleEndian::read_u32(&t[16..]),
+        format: LittleEndian::read_u32(&t[20..]),
+        width: LittleEndian::read_u16(&t[24..]) as u32,
+        height: LittleEndian::read_u16(&t[26..]) as u32,
+    })
+}
@@ -0,0 +1,124 @@
+//! Lay inputs given on the command line: lay files or lay entries of archives.
+
+use super::*;
+use crate::archive::is_archive;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type SharedArchive = Rc<RefCell<Box<dyn Archive>>>;
+
+/// Single lay to process along with the way to get its source png
+pub(crate) enum LayInput {
+    File { path: PathBuf, pairing: Rc<Pairing> },
+    Archive {
+        archive: SharedArchive,
+        archive_path: PathBuf,
+        /// Archive path joined with the entry name, used for reporting
+        path: PathBuf,
+        lay: usize,
+        png: Option<usize>,
+    },
+}
+
+impl LayInput {
+    /// Expand command line paths, each archive yields all of its lay entries
+    /// paired with source images by `pairing`. Archives that can't be opened are returned as errors
+    pub fn expand(
+        paths: &[PathBuf],
+        pairing: &Pairing,
+        limits: &Limits,
+    ) -> Vec<Result<LayInput, (PathBuf, SgSpriteErr)>> {
+        let mut res = Vec::with_capacity(paths.len());
+        let shared = Rc::new(pairing.clone());
+
+        for p in paths {
+            if !is_archive(p) {
+                res.push(Ok(LayInput::File { path: p.clone(), pairing: shared.clone() }));
+                continue;
+            }
+
+            let archive = match open_archive(p, limits) {
+                Ok(a) => a,
+                Err(e) => {
+                    res.push(Err((p.clone(), e)));
+                    continue;
+                }
+            };
+
+            let pairs = archive.lay_pairs_with(pairing);
+            info!("{}: {} entries, {} lay files", p.display(), archive.entry_count(), pairs.len());
+
+            let names: Vec<_> = pairs.iter().map(|(lay, _)| archive.entry_name(*lay).to_string()).collect();
+            let archive = Rc::new(RefCell::new(archive));
+
+            for ((lay, png), name) in pairs.into_iter().zip(names) {
+                res.push(Ok(LayInput::Archive {
+                    archive: archive.clone(),
+                    archive_path: p.clone(),
+                    path: p.join(name),
+                    lay,
+                    png,
+                }));
+            }
+        }
+
+        res
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            LayInput::File { path, .. } | LayInput::Archive { path, .. } => path,
+        }
+    }
+
+    pub fn read_lay(&self) -> Result<Vec<u8>, SgSpriteErr> {
+        match self {
+            LayInput::File { path, .. } => Ok(std::fs::read(path)?),
+            LayInput::Archive { archive, lay, .. } => archive.borrow_mut().read(*lay),
+        }
+    }
+
+    /// Find and decode the source png
+    pub fn source(&self, sprite_name: &str) -> Result<(PathBuf, DrawPrep), SgSpriteErr> {
+        match self {
+            LayInput::File { path, pairing } => {
+                let png = pairing.find_source(path)?;
+                let prep = draw_prep(&png)?;
+                Ok((png, prep))
+            }
+            LayInput::Archive { .. } => {
+                let (png, data) = self.archive_png(sprite_name)?;
+                Ok((png, draw_prep_bytes(&data)?))
+            }
+        }
+    }
+
+    /// Source image size, None if there's no source image
+    pub fn source_dim(&self, sprite_name: &str) -> Result<Option<(u32, u32)>, SgSpriteErr> {
+        let res = match self {
+            LayInput::File { path, pairing } => pairing
+                .find_source(path)
+                .and_then(|src| source_dimensions(&std::fs::read(src)?)),
+            LayInput::Archive { .. } => {
+                self.archive_png(sprite_name).and_then(|(_, data)| source_dimensions(&data))
+            }
+        };
+
+        match res {
+            Ok(dim) => Ok(Some(dim)),
+            Err(SgSpriteErr::NoSourcePng(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn archive_png(&self, sprite_name: &str) -> Result<(PathBuf, Vec<u8>), SgSpriteErr> {
+        match self {
+            LayInput::Archive { archive, archive_path, png: Some(png), .. } => {
+                let mut archive = archive.borrow_mut();
+                let data = archive.read(*png)?;
+                Ok((archive_path.join(archive.entry_name(*png)), data))
+            }
+            _ => Err(SgSpriteErr::NoSourcePng(sprite_name.to_string())),
+        }
+    }
+}
@@ -1,32 +1,79 @@
+//! Sprite layout parser for MAGES. engine.
+//!
+//! The library part exposes the same stages the `sg-sprite` binary goes through:
+//! [`parse`] reads `.lay` files into [`ParsedLay`], [`dep`] resolves sprite
+//! variants into layer lists and [`draw`] composes those layers from the source png.
+//! [`write`] serializes [`ParsedLay`] back into the lay format.
+
 #![allow(dead_code, unused_imports)]
 
 use lazy_format::lazy_format;
+use log::{debug, error, info, trace, warn};
 use std::env;
 use std::fmt::Display;
 use std::format as fmt;
 use std::path::{Path, PathBuf};
 use std::fs::{File, DirEntry};
-use std::io;
+use std::io::{self, Write};
 use structopt::StructOpt;
 
-mod dep;
-mod draw;
-mod parse;
+#[macro_use]
+mod error;
+pub mod archive;
+mod astc;
+mod bcn;
+pub mod bntx;
+pub mod check;
+pub mod cpk;
+pub mod dds;
+pub mod dep;
+pub mod draw;
+pub mod dump;
+pub mod event;
+pub mod gxt;
+mod input;
+pub mod logger;
+pub mod mpk;
+pub mod pairing;
+pub mod parse;
+pub mod profile;
+mod pvrtc;
+mod swizzle;
 mod util;
-
-use dep::*;
-use draw::*;
-use parse::*;
+pub mod validate;
+pub mod write;
+
+pub use archive::{open_archive, Archive};
+pub use check::{check_file, check_lay, Anomaly, LayReport};
+pub use dep::{DepGraph, DepNode};
+pub use error::{LaySection, SgSpriteErr};
+pub use event::{Event, LogObserver, Observer};
+pub use dump::dump_lay;
+pub use draw::{
+    draw_prep, draw_prep_bytes, draw_sprites, save_png, source_dimensions, DirSink, DrawPrep, SpriteSink,
+};
+pub use cpk::{decompress_crilayla, CpkArchive, CpkEntry};
+pub use bntx::decode_bntx;
+pub use dds::decode_dds;
+pub use gxt::decode_gxt;
+pub use mpk::{MpkArchive, MpkEntry};
+pub use pairing::{PairRule, Pairing};
+pub use profile::{detect_profile, GameProfile};
+pub use parse::{
+    decompress_lay, detect_compression, detect_compression_len, detect_endian, parse_lay, parse_lay_bytes,
+    parse_lay_read, Chunk, Compression, Endian, LayWarning, Limits, ParseOpts, ParsedLay, Sprite, SpriteT,
+    UnknownTypePolicy, DEFAULT_BLOCK_SIZE,
+};
+pub use validate::{validate_lay, validate_source};
+pub use write::{write_lay, write_lay_bytes};
+use input::LayInput;
 use util::*;
 
-pub type SgSpriteErr = anyhow::Error;
-use anyhow::{anyhow as raise_e, bail as raise};
-
 pub fn print_err(e: impl Display) {
-    eprintln!("[E] {}", e);
+    error!("{}", e);
 }
 
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Default)]
 #[structopt()]
 pub struct Opts {
     /// Output dir
@@ -37,7 +84,7 @@ pub struct Opts {
     #[structopt(short, long)]
     pub limit: Option<usize>,
 
-    /// .lay files to parse
+    /// .lay files to parse, .mpk and .cpk archives are searched for lay/png pairs
     #[structopt(name = "LAY_FILES", parse(from_os_str))]
     pub lay_files: Vec<PathBuf>,
 
@@ -45,93 +92,412 @@ pub struct Opts {
     /// Do not compose actual images
     #[structopt(long)]
     pub dry_run: bool,
+
+    /// Print less messages (-q: warnings and errors, -qq: errors only, -qqq: nothing)
+    #[structopt(short, long, parse(from_occurrences))]
+    pub quiet: u8,
+
+    /// Print more messages (-v: debug, -vv: trace)
+    #[structopt(short, long, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// Print messages as JSON lines
+    #[structopt(long)]
+    pub log_json: bool,
+
+    /// Stop on the first failed .lay file
+    #[structopt(long)]
+    pub fail_fast: bool,
+
+    /// Dump parsed lay files as JSON instead of composing sprites.
+    /// Writes <name>.json into the output dir if it's specified, stdout otherwise
+    #[cfg(feature = "json")]
+    #[structopt(long)]
+    pub dump_json: bool,
+
+    /// Check lay files and their source pngs for format anomalies
+    /// and print a report instead of composing sprites
+    #[structopt(long)]
+    pub check: bool,
+
+    /// Print --check report as JSON
+    #[cfg(feature = "json")]
+    #[structopt(long)]
+    pub check_json: bool,
+
+    /// Print annotated hex dump of (decompressed) lay files instead of composing sprites
+    #[structopt(long)]
+    pub dump: bool,
+
+    /// What to do with sprites of unknown type: fail the file (error),
+    /// drop them (skip) or draw them alone (standalone)
+    #[structopt(long, default_value = "error", possible_values = &["error", "skip", "standalone"])]
+    pub unknown_types: UnknownTypePolicy,
+
+    /// Chunk block size in pixels, detected from chunk coordinates if not specified
+    #[structopt(long)]
+    pub block_size: Option<u32>,
+
+    /// Byte order of lay files, detected from the header if not specified
+    #[structopt(long, possible_values = &["little", "big"])]
+    pub endian: Option<Endian>,
+
+    /// Source image naming rules in order of preference: exact (CRS_A.png),
+    /// underscore (CRS_A.png for CRS_A_.lay), image-suffix (CRS_A (Image 0).png).
+    /// Defaults to the --game rules or exact,underscore,image-suffix
+    #[structopt(long, require_delimiter = true, possible_values = &["exact", "underscore", "image-suffix"])]
+    pub pair_rules: Vec<PairRule>,
+
+    /// Regex matched against source image names without extension, preferred over the other rules
+    /// for the same extension.
+    /// Its first group should capture the lay file name without .lay, e.g. '^(.+)_src$'
+    #[structopt(long)]
+    pub pair_regex: Option<String>,
+
+    /// Source images of lay files: CSV with lay,png lines or JSON object (with json feature).
+    /// Paths are relative to the manifest, unlisted lay files are paired by the rules
+    #[structopt(long, parse(from_os_str))]
+    pub manifest: Option<PathBuf>,
+
+    /// Game profile setting compression, byte order, source naming and screen size:
+    /// sg, sg-hiyoku, sg-lbp, sg0, sg0-ps3, cc, oregairu. Explicit options take precedence.
+    /// If not specified, the profile is detected per file where possible and used for --check
+    #[structopt(long, parse(try_from_str = GameProfile::by_id))]
+    pub game: Option<&'static GameProfile>,
+
+    /// Fail lay files with more sprites than this [default: 65536]
+    #[structopt(long)]
+    pub max_sprites: Option<u32>,
+
+    /// Fail lay files with more chunks than this [default: 1048576]
+    #[structopt(long)]
+    pub max_chunks: Option<u32>,
+
+    /// Fail lay files larger than this many bytes after decompression [default: 64 MiB]
+    #[structopt(long)]
+    pub max_decompressed: Option<u64>,
+
+    /// Fail lay files with sprite canvas wider or taller than this many pixels [default: 16384]
+    #[structopt(long)]
+    pub max_canvas: Option<u32>,
+}
+
+impl Opts {
+    #[cfg(feature = "json")]
+    fn json_mode(&self) -> bool {
+        self.dump_json
+    }
+
+    #[cfg(not(feature = "json"))]
+    fn json_mode(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "json")]
+    fn check_json_mode(&self) -> bool {
+        self.check_json
+    }
+
+    #[cfg(not(feature = "json"))]
+    fn check_json_mode(&self) -> bool {
+        false
+    }
+
+    fn check_mode(&self) -> bool {
+        self.check || self.check_json_mode()
+    }
+
+    /// Whether sprites are composed in this run
+    fn composes(&self) -> bool {
+        !self.dry_run && !self.json_mode() && !self.check_mode() && !self.dump
+    }
+
+    /// Parser options according to --unknown-types, --block-size, --endian, --game and --max-* limits
+    pub fn parse_opts(&self) -> ParseOpts {
+        let defaults = Limits::default();
+        let limits = Limits {
+            sprites: self.max_sprites.unwrap_or(defaults.sprites),
+            chunks: self.max_chunks.unwrap_or(defaults.chunks),
+            decompressed: self.max_decompressed.unwrap_or(defaults.decompressed),
+            canvas: self.max_canvas.unwrap_or(defaults.canvas),
+        };
+        let opts = ParseOpts {
+            unknown_types: self.unknown_types,
+            block_size: self.block_size,
+            endian: self.endian,
+            compression: None,
+            limits,
+        };
+
+        match self.game {
+            Some(game) => game.parse_opts(opts),
+            None => opts,
+        }
+    }
+
+    /// Source pairing according to --pair-rules, --pair-regex and --manifest.
+    /// The regex comes first, followed by --pair-rules, --game or default rules
+    pub fn pairing(&self) -> Result<Pairing, SgSpriteErr> {
+        let mut rules = Vec::new();
+        if let Some(re) = &self.pair_regex {
+            rules.push(PairRule::regex(re)?);
+        }
+        match (self.pair_rules.as_slice(), self.game) {
+            ([], Some(game)) => rules.extend_from_slice(game.pair_rules),
+            ([], None) => rules.extend_from_slice(PairRule::DEFAULT),
+            (r, _) => rules.extend_from_slice(r),
+        }
+
+        let mut pairing = Pairing::new(rules);
+        if let Some(m) = &self.manifest {
+            pairing.load_manifest(m)?;
+        }
+        Ok(pairing)
+    }
+
+    /// Logger configured according to -q/-v/--log-json
+    pub fn logger(&self) -> logger::Logger {
+        logger::Logger::new(self.verbose as i32 - self.quiet as i32, self.log_json)
+    }
 }
 
 const LAY_EXT: &[&str] = &["_.lay", ".lay"];
 
-pub fn lib_main(o: &Opts) -> Result<(), SgSpriteErr> {
+/// Results of the [`lib_main`] run
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    /// Count of lay files given
+    pub total: usize,
+    /// Count of lay files processed, including failed ones
+    pub processed: usize,
+    /// Count of sprite variants drawn
+    pub rendered: usize,
+    /// Count of lay files with format anomalies in `--check` mode
+    pub anomalous: usize,
+    /// Failed lay files with their errors
+    pub failed: Vec<(PathBuf, SgSpriteErr)>,
+}
+
+impl RunSummary {
+    /// 0 if everything succeeded, 2 if some files failed, were skipped or have anomalies,
+    /// 1 if all of them failed
+    pub fn exit_code(&self) -> i32 {
+        match self.failed.len() {
+            0 if self.processed == self.total && self.anomalous == 0 => 0,
+            f if f == self.total => 1,
+            _ => 2,
+        }
+    }
+
+    /// Log counts and failed files with their errors, so they can be found at the end of a long run
+    pub fn log(&self) {
+        info!(
+            "Processed {}/{} lay files, {} variants rendered, {} failed",
+            self.processed, self.total, self.rendered, self.failed.len()
+        );
+
+        for (path, e) in &self.failed {
+            error!("Failed {}: {}", path.display(), e);
+        }
+        if self.anomalous > 0 {
+            warn!("{} lay files have format anomalies", self.anomalous);
+        }
+    }
+}
+
+pub fn lib_main(o: &Opts) -> Result<RunSummary, SgSpriteErr> {
+    lib_main_with(o, &mut LogObserver)
+}
+
+/// Same as [`lib_main`], reporting progress to `obs`
+pub fn lib_main_with(o: &Opts, obs: &mut dyn Observer) -> Result<RunSummary, SgSpriteErr> {
     let layouts = &o.lay_files;
     let out_dir = o.dir.as_ref();
 
     match out_dir {
-        Some(d) if !d.is_dir() => raise!("out_dir isn't a directory"),
-        None if !o.dry_run => {
-            raise!("Output dir should be specified (-d)\nSee --help for details");
+        Some(d) if !d.is_dir() => raise!(SgSpriteErr::Usage("out_dir isn't a directory")),
+        None if o.composes() => {
+            raise!(SgSpriteErr::Usage("Output dir should be specified (-d)\nSee --help for details"));
         }
         _ => (),
     }
 
-    let total = o.lay_files.len();
+    if o.block_size == Some(0) {
+        raise!(SgSpriteErr::Usage("block size should be positive"));
+    }
+
+    let inputs = LayInput::expand(layouts, &o.pairing()?, &o.parse_opts().limits);
+    let total = inputs.len();
 
     if total == 0 {
-        raise!("no .lay files provided");
+        raise!(SgSpriteErr::Usage("no .lay files provided"));
+    }
+
+    let mut sink = out_dir.filter(|_| o.composes()).map(|d| DirSink(d.clone()));
+    let mut summary = RunSummary { total, ..RunSummary::default() };
+    let mut reports = Vec::new();
+
+    for (i, input) in inputs.into_iter().enumerate() {
+        let sink = sink.as_mut().map(|s| s as &mut dyn SpriteSink);
+        summary.processed += 1;
+
+        let (lay_path, res) = match input {
+            Ok(input) => {
+                let res = if o.check_mode() {
+                    check_in(o, &input, (i, total), obs, &mut reports)
+                } else if o.dump {
+                    dump_in(o, &input, (i, total), obs)
+                } else {
+                    lay_in(o, sink, &input, (i, total), obs)
+                };
+
+                (input.path().to_path_buf(), res)
+            }
+            Err((archive_path, e)) => (archive_path, Err(e)),
+        };
+
+        obs.event(&Event::LayFinished { path: &lay_path, result: res.as_ref().map(|r| *r) });
+
+        match res {
+            Ok(rendered) => summary.rendered += rendered,
+            Err(e) => {
+                summary.failed.push((lay_path, e));
+
+                if o.fail_fast {
+                    break;
+                }
+            }
+        }
     }
 
-    let status = |c: usize| move |t: &str| println!("[{}/{}] {}", c + 1, total, t);
+    summary.anomalous = reports.iter().filter(|r| !r.anomalies.is_empty()).count();
 
-    for i in 0..layouts.len() {
-        let lay_path = &layouts[i];
-        if let Err(e) = lay_in(out_dir, lay_path, o.limit, status(i)) {
-            print_err(e);
-            print_err(format_args!("({})", lay_path.display()));
+    #[cfg(feature = "json")]
+    {
+        if o.check_json {
+            check::print_reports_json(&reports)?;
+            return Ok(summary);
         }
     }
 
-    Ok(())
+    if o.check_mode() {
+        check::print_reports(&reports);
+    }
+
+    Ok(summary)
 }
 
-fn lay_in(
-    out_dir: Option<impl AsRef<Path>>,
-    lay_file: &Path,
-    limit: Option<usize>,
-    status_cb: impl Fn(&str),
-) -> Result<(), SgSpriteErr> {
-    let limit = limit.unwrap_or(0);
-
-    let (lay_filename, lay_ext) = lay_file
+/// Sprite name, i.e. lay file name without extension
+fn lay_name(lay_file: &Path) -> Result<&str, SgSpriteErr> {
+    lay_file
         .file_name()
         .and_then(|n| n.to_str())
-        .and_then(|f| LAY_EXT.iter().find(|e| f.ends_with(**e)).map(|e| (f, e)))
-        .ok_or_else(|| raise_e!("not a lay file"))?;
+        .and_then(sprite_name)
+        .ok_or(SgSpriteErr::NotLayFile)
+}
 
-    let sprite_name =
-        lay_filename.trim_end_matches(lay_ext);
+/// Lay file name without extension, None if it's not a lay file name
+fn sprite_name(lay_filename: &str) -> Option<&str> {
+    LAY_EXT
+        .iter()
+        .find(|e| lay_filename.ends_with(**e))
+        .map(|e| lay_filename.trim_end_matches(e))
+}
 
-    status_cb(sprite_name);
+fn check_in(
+    o: &Opts,
+    input: &LayInput,
+    (index, total): (usize, usize),
+    obs: &mut dyn Observer,
+    reports: &mut Vec<LayReport>,
+) -> Result<usize, SgSpriteErr> {
+    let lay_file = input.path();
+    let sprite_name = lay_name(lay_file)?;
+    obs.event(&Event::LayStarted { index, total, path: lay_file, name: sprite_name });
+
+    let res = input.read_lay().and_then(|data| {
+        let lay = o.parse_opts().parse_lay_bytes(&data)?;
+        let screen = o.game.or_else(|| detect_profile(&lay)).map(|g| g.screen);
+        Ok(check::check_with_source(&lay, input.source_dim(sprite_name)?, screen))
+    });
+
+    let (anomalies, error) = match &res {
+        Ok(a) => (a.clone(), None),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+
+    reports.push(LayReport { path: lay_file.to_path_buf(), anomalies, error });
+    res.map(|_| 0)
+}
+
+fn dump_in(
+    o: &Opts,
+    input: &LayInput,
+    (index, total): (usize, usize),
+    obs: &mut dyn Observer,
+) -> Result<usize, SgSpriteErr> {
+    let lay_file = input.path();
+    let sprite_name = lay_name(lay_file)?;
+    obs.event(&Event::LayStarted { index, total, path: lay_file, name: sprite_name });
+
+    let data = input.read_lay()?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    writeln!(out, "# {}", lay_file.display())?;
+    dump_lay(&data, &o.parse_opts(), &mut out)?;
+    writeln!(out)?;
+    Ok(0)
+}
+
+fn lay_in(
+    o: &Opts,
+    sink: Option<&mut dyn SpriteSink>,
+    input: &LayInput,
+    (index, total): (usize, usize),
+    obs: &mut dyn Observer,
+) -> Result<usize, SgSpriteErr> {
+    let limit = o.limit.unwrap_or(0);
+    let mut rendered = 0;
+    let mut draw_err = None;
+
+    let lay_file = input.path();
+    let sprite_name = lay_name(lay_file)?;
+    obs.event(&Event::LayStarted { index, total, path: lay_file, name: sprite_name });
+
+    let lay = o.parse_opts().parse_lay_bytes(&input.read_lay()?)?;
+    if let (None, Some(game)) = (o.game, detect_profile(&lay)) {
+        debug!("{}: looks like {}", sprite_name, game.title);
+    }
+    lay.warnings.iter().for_each(|w| obs.event(&Event::Warning(w)));
+
+    // dump is written as parsed, broken chunk ranges included
+    #[cfg(feature = "json")]
+    {
+        if o.dump_json {
+            write_json(o.dir.as_ref(), sprite_name, &lay)?;
+            return Ok(0);
+        }
+    }
+
+    validate_lay(&lay)?;
 
-    let lay = parse_lay(&mut File::open(lay_file)?)?;
     let graph = DepGraph::resolve_dep_graph(&lay);
-    let leaves = graph.get_leaf_sprites();
-
-    if let Some(out_dir) = out_dir {
-        let src_image_path: PathBuf = {
-            let mut path_buf = lay_file.canonicalize().expect("Can't canonicalize .lay path");
-            let parent_dir = path_buf.parent().expect("No parent dir");
-            // bail out right away if there are problems with path resolution
-
-            let src_filename = parent_dir.read_dir()
-                .unwrap()
-                .flatten()
-                .find(|f|
-                    f.file_name().to_str()
-                        .map(|n| n.starts_with(sprite_name) && n.ends_with(".png"))
-                        .unwrap_or(false)
-                )
-                .ok_or_else(|| raise_e!("No corresponding png file"))?;
-
-            path_buf.pop();
-            path_buf.push(src_filename.file_name());
-            path_buf
-        };
+    let leaves: Vec<_> = graph.get_leaf_sprites().collect();
 
-        println!("[I] Using source file: {}",
-                 src_image_path.file_name().unwrap().to_str().unwrap_or("???"));
+    if let Some(sink) = sink {
+        let (src_image_path, mut src_image) = input.source(sprite_name)?;
+        obs.event(&Event::SourceChosen { path: &src_image_path });
 
-        let mut src_image = draw_prep(&src_image_path)?;
+        validate_source(&lay, src_image.dimensions())?;
+        let variants = match limit {
+            0 => leaves.len(),
+            l => leaves.len().min(l),
+        };
 
-        for (pass, sp) in leaves.enumerate() {
+        for (pass, sp) in leaves.into_iter().enumerate() {
             if limit > 0 && pass >= limit {
-                eprintln!("[I] Limit reached, proceeding to next sprite");
+                obs.event(&Event::LimitReached { limit });
                 break;
             }
 
@@ -141,14 +507,24 @@ fn lay_in(
                 SpriteT::Sub => ("s{}", s.id),
                 SpriteT::Overlay => ("o{}", s.id),
                 SpriteT::Dep { exact_type: st, depends_on: dep } => ("t{}_d{}_{}", st, dep, s.id),
+                SpriteT::Unknown(st) => ("u{}_{}", st, s.id),
             });
 
             let layers = graph.resolve_layers(sp)?;
-            let mut out_path = out_dir.as_ref().to_path_buf();
-            out_path.push(fmt!("{}_{}.png", sprite_name, name_suf));
-
-            if let Err(e) = draw_sprites(&mut src_image, &out_path, layers.as_ref(), pass + 1, &lay) {
-                print_err(e);
+            let out_name = fmt!("{}_{}", sprite_name, name_suf);
+
+            let drawn = draw_sprites(&mut src_image, layers.as_ref(), pass + 1, &lay)
+                .and_then(|sprite| sink.put(&out_name, sprite));
+
+            match drawn {
+                Ok(()) => {
+                    rendered += 1;
+                    obs.event(&Event::VariantDrawn { name: &out_name, n: pass + 1, of: variants });
+                }
+                Err(e) => {
+                    obs.event(&Event::VariantFailed { name: &out_name, error: &e });
+                    draw_err.get_or_insert(e);
+                }
             }
         }
     } else {
@@ -157,5 +533,26 @@ fn lay_in(
         }
     }
 
-    Ok(())
+    // remaining variants are still drawn, but the file is reported as failed
+    match draw_err {
+        Some(e) => Err(e),
+        None => Ok(rendered),
+    }
+}
+
+#[cfg(feature = "json")]
+fn write_json(out_dir: Option<&PathBuf>, name: &str, lay: &ParsedLay) -> Result<(), SgSpriteErr> {
+    let written = match out_dir {
+        Some(d) => {
+            let out = File::create(d.join(fmt!("{}.json", name)))?;
+            serde_json::to_writer_pretty(io::BufWriter::new(out), lay)
+        }
+        None => {
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            serde_json::to_writer_pretty(&mut out, lay).map(|_| println!())
+        }
+    };
+
+    written.map_err(|e| SgSpriteErr::Io(e.into()))
 }
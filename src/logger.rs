@@ -0,0 +1,73 @@
+//! Stderr logger used by the binary. Library users may install any other `log` implementation.
+
+use super::*;
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::io::Write;
+
+pub struct Logger {
+    level: LevelFilter,
+    machine: bool,
+}
+
+impl Logger {
+    /// `verbosity` is relative to Info: -1 is Warn, 1 is Debug, etc
+    pub fn new(verbosity: i32, machine: bool) -> Self {
+        let level = match verbosity {
+            v if v <= -3 => LevelFilter::Off,
+            -2 => LevelFilter::Error,
+            -1 => LevelFilter::Warn,
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        };
+
+        Logger { level, machine }
+    }
+
+    /// Install as the global logger
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        let level = self.level;
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, m: &Metadata) -> bool {
+        m.level() <= self.level
+    }
+
+    fn log(&self, r: &Record) {
+        if !self.enabled(r.metadata()) {
+            return;
+        }
+
+        let stderr = io::stderr();
+        let mut out = stderr.lock();
+
+        let _ = if self.machine {
+            let msg = r.args().to_string();
+            writeln!(
+                out,
+                "{{\"level\":\"{}\",\"target\":\"{}\",\"msg\":\"{}\"}}",
+                r.level(),
+                r.target(),
+                JsonEsc(&msg)
+            )
+        } else {
+            let tag = match r.level() {
+                Level::Error => 'E',
+                Level::Warn => 'W',
+                Level::Info => 'I',
+                Level::Debug => 'D',
+                Level::Trace => 'T',
+            };
+            writeln!(out, "[{}] {}", tag, r.args())
+        };
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
@@ -1,8 +1,21 @@
 use sg_sprite::*;
+use std::process;
 use structopt::StructOpt;
 
 fn main() {
-    if let Err(e) = lib_main(&Opts::from_args()) {
-        print_err(e);
-    }
+    let opts = Opts::from_args();
+    opts.logger().init().expect("logger is already set");
+
+    let code = match lib_main(&opts) {
+        Ok(summary) => {
+            summary.log();
+            summary.exit_code()
+        }
+        Err(e) => {
+            print_err(e);
+            1
+        }
+    };
+
+    process::exit(code);
 }
@@ -0,0 +1,171 @@
+//! MAGES. `.mpk` archive reader.
+//!
+//! Layout (little-endian):
+//!
+//! - header, `0x40` bytes: `[4:"MPK\0"][u16:ver_minor][u16:ver_major][entry_count]`,
+//!   entry count is `u32` in v1 and `u64` in v2
+//! - entry table at `0x40`, `0x100` bytes per entry:
+//!   - v1: `[u32:id][u32:offset][u32:size][u32:size_uncompressed][16:reserved][0xE0:name]`
+//!   - v2: `[u32:compression][u32:id][u64:offset][u64:size][u64:size_uncompressed][0xE0:name]`
+//!
+//! Names are NUL-padded. Compressed entries are zlib streams.
+
+use super::*;
+use byteorder::{LittleEndian, ReadBytesExt};
+use libflate::zlib;
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom};
+
+const MAGIC: &[u8; 4] = b"MPK\0";
+const HEADER_SZ: u64 = 0x40;
+const ENTRY_SZ: u64 = 0x100;
+const NAME_SZ: usize = 0xE0;
+
+/// Archive entry
+#[derive(Clone, Debug)]
+pub struct MpkEntry {
+    pub id: u32,
+    pub name: String,
+    /// Absolute offset of the entry data
+    pub offset: u64,
+    /// Size of the stored data
+    pub size: u64,
+    pub size_uncompressed: u64,
+    pub compressed: bool,
+}
+
+/// Opened `.mpk` archive, entry data is read on demand
+pub struct MpkArchive<R> {
+    src: R,
+    entries: Vec<MpkEntry>,
+    /// Max size of decompressed entry
+    limit: u64,
+}
+
+impl<R: Read + Seek> MpkArchive<R> {
+    /// Read archive header and entry table
+    pub fn open(src: R) -> Result<Self, SgSpriteErr> {
+        MpkArchive::open_with(src, &Limits::default())
+    }
+
+    /// Same as [`MpkArchive::open`], compressed entries are decompressed up to `limits.decompressed`
+    pub fn open_with(mut src: R, limits: &Limits) -> Result<Self, SgSpriteErr> {
+        let file_len = src.seek(SeekFrom::End(0))?;
+        src.seek(SeekFrom::Start(0))?;
+
+        let mut magic = [0u8; 4];
+        src.read_exact(&mut magic).map_err(archive_err)?;
+        if &magic != MAGIC {
+            raise!(SgSpriteErr::BadArchive("not an mpk archive".to_string()));
+        }
+
+        let ver_minor = src.read_u16::<LittleEndian>().map_err(archive_err)?;
+        let ver_major = src.read_u16::<LittleEndian>().map_err(archive_err)?;
+        let entry_count = match ver_major {
+            1 => src.read_u32::<LittleEndian>().map_err(archive_err)? as u64,
+            2 => src.read_u64::<LittleEndian>().map_err(archive_err)?,
+            _ => raise!(SgSpriteErr::BadArchive(fmt!("unsupported mpk version {}.{}", ver_major, ver_minor))),
+        };
+
+        let table_end = entry_count.checked_mul(ENTRY_SZ).and_then(|sz| sz.checked_add(HEADER_SZ));
+        if !matches!(table_end, Some(end) if end <= file_len) {
+            raise!(SgSpriteErr::BadArchive(fmt!("entry table ({} entries) is out of file", entry_count)));
+        }
+
+        debug!("mpk v{}.{}, {} entries", ver_major, ver_minor, entry_count);
+        src.seek(SeekFrom::Start(HEADER_SZ))?;
+
+        // entry table fits into the file, so does the entry count into usize
+        let mut entries = Vec::with_capacity(usize::try_from(entry_count).unwrap_or(0));
+        let mut buf = [0u8; ENTRY_SZ as usize];
+        for _ in 0..entry_count {
+            src.read_exact(&mut buf).map_err(archive_err)?;
+            let e = match ver_major {
+                1 => read_entry_v1(&buf),
+                _ => read_entry_v2(&buf),
+            }?;
+
+            let in_file = e.offset.checked_add(e.size).map(|end| end <= file_len).unwrap_or(false);
+            if !in_file {
+                raise!(SgSpriteErr::BadArchive(fmt!("entry {} is out of file", e.name)));
+            }
+
+            entries.push(e);
+        }
+
+        Ok(MpkArchive { src, entries, limit: limits.decompressed })
+    }
+
+    pub fn entries(&self) -> &[MpkEntry] {
+        &self.entries
+    }
+}
+
+impl<R: Read + Seek> Archive for MpkArchive<R> {
+    fn entry_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn entry_name(&self, index: usize) -> &str {
+        &self.entries[index].name
+    }
+
+    fn read(&mut self, index: usize) -> Result<Vec<u8>, SgSpriteErr> {
+        let e = &self.entries[index];
+        self.src.seek(SeekFrom::Start(e.offset))?;
+
+        let mut stored = (&mut self.src).take(e.size);
+        let capacity = e.size_uncompressed.min(e.size.saturating_mul(16)).min(self.limit);
+        let mut buf = Vec::with_capacity(capacity as usize);
+
+        if e.compressed {
+            let limit = self.limit;
+            zlib::Decoder::new(stored)
+                .and_then(|z| z.take(limit.saturating_add(1)).read_to_end(&mut buf))
+                .map_err(|err| SgSpriteErr::BadArchive(fmt!("entry {}: {}", e.name, err)))?;
+            if buf.len() as u64 > limit {
+                raise!(SgSpriteErr::TooLarge { limit });
+            }
+        } else {
+            stored.read_to_end(&mut buf)?;
+        }
+
+        Ok(buf)
+    }
+}
+
+#[inline]
+fn archive_err(e: io::Error) -> SgSpriteErr {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => SgSpriteErr::BadArchive("truncated mpk header".to_string()),
+        _ => SgSpriteErr::Io(e),
+    }
+}
+
+fn entry_name(raw: &[u8]) -> String {
+    let len = raw.iter().position(|b| *b == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..len]).into_owned()
+}
+
+fn read_entry_v1(buf: &[u8]) -> Result<MpkEntry, SgSpriteErr> {
+    let mut b = buf;
+    let id = b.read_u32::<LittleEndian>()?;
+    let offset = b.read_u32::<LittleEndian>()? as u64;
+    let size = b.read_u32::<LittleEndian>()? as u64;
+    let size_uncompressed = b.read_u32::<LittleEndian>()? as u64;
+    let name = entry_name(&buf[buf.len() - NAME_SZ..]);
+
+    Ok(MpkEntry { id, name, offset, size, size_uncompressed, compressed: size != size_uncompressed })
+}
+
+fn read_entry_v2(buf: &[u8]) -> Result<MpkEntry, SgSpriteErr> {
+    let mut b = buf;
+    let compression = b.read_u32::<LittleEndian>()?;
+    let id = b.read_u32::<LittleEndian>()?;
+    let offset = b.read_u64::<LittleEndian>()?;
+    let size = b.read_u64::<LittleEndian>()?;
+    let size_uncompressed = b.read_u64::<LittleEndian>()?;
+    let name = entry_name(&buf[buf.len() - NAME_SZ..]);
+
+    Ok(MpkEntry { id, name, offset, size, size_uncompressed, compressed: compression != 0 })
+}
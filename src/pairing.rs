@@ -0,0 +1,195 @@
+//! Pairing of lay files with their source images.
+//!
+//! Source image candidates are files next to the lay file with one of the source extensions.
+//! A candidate is ranked by extension (png first), then by the first [`PairRule`] its name
+//! (without extension) satisfies, then by name, so the choice doesn't depend on directory order.
+//! Rules compare it with the lay file name without `.lay`, trailing underscore included.
+//! Manifest entries, if any, take precedence over the rules.
+
+use super::*;
+use regex::Regex;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Source image extensions in order of preference
+const SOURCE_EXT: &[&str] = &[".png", ".gxt", ".dds", ".bntx"];
+
+/// Scarlet exports source images as `<name> (Image 0).png`
+const IMAGE_SUFFIX: &str = "(Image 0)";
+
+/// How a source image name (without extension) relates to the lay name (without `.lay`)
+#[derive(Clone, Debug)]
+pub enum PairRule {
+    /// Same name: `CRS_A.lay` and `CRS_A.png`
+    Exact,
+    /// Trailing underscore of the lay name is dropped: `CRS_A_.lay` and `CRS_A.png`
+    Underscore,
+    /// Scarlet suffix: `CRS_A.lay` and `CRS_A (Image 0).png`, the space is optional
+    ImageSuffix,
+    /// The first group of the regex, matched against the source name, is the lay name
+    Regex(Regex),
+}
+
+impl PairRule {
+    /// Rules used if none are given
+    pub const DEFAULT: &'static [PairRule] = &[PairRule::Exact, PairRule::Underscore, PairRule::ImageSuffix];
+
+    /// Regex rule, the regex should have at least one group
+    pub fn regex(re: &str) -> Result<PairRule, SgSpriteErr> {
+        let re = Regex::new(re).map_err(|e| SgSpriteErr::BadPairing(fmt!("bad regex: {}", e)))?;
+        if re.captures_len() < 2 {
+            raise!(SgSpriteErr::BadPairing(fmt!("regex {} has no group to capture the sprite name", re)));
+        }
+        Ok(PairRule::Regex(re))
+    }
+
+    /// Whether `source` (name without extension) is the source image of `lay_name`
+    pub fn matches(&self, source: &str, lay_name: &str) -> bool {
+        match self {
+            PairRule::Exact => source == lay_name,
+            PairRule::Underscore => lay_name.strip_suffix('_') == Some(source),
+            PairRule::ImageSuffix => {
+                source.strip_prefix(lay_name).map(|s| s.trim_start()) == Some(IMAGE_SUFFIX)
+            }
+            PairRule::Regex(re) => {
+                let name = re.captures(source).and_then(|c| c.get(1));
+                name.map(|n| n.as_str()) == Some(lay_name)
+            }
+        }
+    }
+}
+
+impl FromStr for PairRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(PairRule::Exact),
+            "underscore" => Ok(PairRule::Underscore),
+            "image-suffix" => Ok(PairRule::ImageSuffix),
+            _ => Err(fmt!("unknown pairing rule {}", s)),
+        }
+    }
+}
+
+/// Pairing rules in order of preference, along with explicit lay to source mapping
+#[derive(Clone, Debug)]
+pub struct Pairing {
+    rules: Vec<PairRule>,
+    /// Canonical lay path to source image path
+    manifest: HashMap<PathBuf, PathBuf>,
+}
+
+impl Default for Pairing {
+    fn default() -> Self {
+        Pairing::new(PairRule::DEFAULT.to_vec())
+    }
+}
+
+impl Pairing {
+    pub fn new(rules: Vec<PairRule>) -> Self {
+        Pairing { rules, manifest: HashMap::new() }
+    }
+
+    /// Preference of `filename` as the source image of `lay_name` as (extension, rule) indices,
+    /// lower is better. None if it's not a source image of the lay file
+    pub fn rank(&self, filename: &str, lay_name: &str) -> Option<(usize, usize)> {
+        let ext = SOURCE_EXT.iter().position(|e| filename.ends_with(e))?;
+        let source = &filename[..filename.len() - SOURCE_EXT[ext].len()];
+        let rule = self.rules.iter().position(|r| r.matches(source, lay_name))?;
+        Some((ext, rule))
+    }
+
+    /// Best source image of `lay_name` among `names`, as an index into `names`
+    pub fn choose<'a>(&self, names: impl IntoIterator<Item = &'a str>, lay_name: &str) -> Option<usize> {
+        names
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, n)| self.rank(n, lay_name).map(|rank| (rank, n, i)))
+            .min()
+            .map(|(_, _, i)| i)
+    }
+
+    /// Source image of the lay file: the manifest entry if there is one,
+    /// the best ranked file in the lay file directory otherwise
+    pub fn find_source(&self, lay_file: &Path) -> Result<PathBuf, SgSpriteErr> {
+        // bail out right away if there are problems with path resolution
+        let mut path_buf = lay_file.canonicalize()?;
+        if let Some(src) = self.manifest.get(&path_buf) {
+            return Ok(src.clone());
+        }
+
+        let lay_name = path_buf.file_name().and_then(|n| n.to_str()).and_then(lay_stem);
+        let lay_name = lay_name.ok_or(SgSpriteErr::NotLayFile)?;
+        let parent_dir = path_buf.parent().expect("No parent dir");
+        let names: Vec<_> = parent_dir.read_dir()?.flatten().filter_map(|f| f.file_name().into_string().ok()).collect();
+        let src = self
+            .choose(names.iter().map(String::as_str), lay_name)
+            .ok_or_else(|| SgSpriteErr::NoSourcePng(lay_name.to_string()))?;
+
+        path_buf.pop();
+        path_buf.push(&names[src]);
+        Ok(path_buf)
+    }
+
+    /// Add entries of the manifest file: CSV with `lay,png` lines or, with `json` feature,
+    /// a JSON object of `"lay": "png"` if the file has `.json` extension.
+    /// Relative paths are relative to the manifest, listed lay files should exist
+    pub fn load_manifest(&mut self, manifest: &Path) -> Result<(), SgSpriteErr> {
+        let text = std::fs::read_to_string(manifest)?;
+        let entries = if manifest.extension() == Some("json".as_ref()) {
+            manifest_json(&text)?
+        } else {
+            manifest_csv(&text)?
+        };
+
+        let base = manifest.parent().unwrap_or_else(|| Path::new(""));
+        for (lay, src) in entries {
+            let lay_path = base.join(&lay).canonicalize().map_err(|e| {
+                SgSpriteErr::BadPairing(fmt!("manifest lay file {}: {}", lay, e))
+            })?;
+            self.manifest.insert(lay_path, base.join(src));
+        }
+
+        Ok(())
+    }
+}
+
+/// Lay file name without `.lay`, the name pairing rules match against
+pub(crate) fn lay_stem(lay_filename: &str) -> Option<&str> {
+    lay_filename.strip_suffix(".lay")
+}
+
+/// `lay,png` lines, blank lines and `#` comments are skipped, so is the `lay,png` header.
+/// Fields aren't quoted, the lay path can't contain commas
+fn manifest_csv(text: &str) -> Result<Vec<(String, String)>, SgSpriteErr> {
+    let mut res = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (i == 0 && line == "lay,png") {
+            continue;
+        }
+
+        match line.split_once(',') {
+            Some((lay, src)) if !lay.trim().is_empty() && !src.trim().is_empty() => {
+                res.push((lay.trim().to_string(), src.trim().to_string()))
+            }
+            _ => raise!(SgSpriteErr::BadPairing(fmt!("manifest line {}: expected lay,png", i + 1))),
+        }
+    }
+
+    Ok(res)
+}
+
+#[cfg(feature = "json")]
+fn manifest_json(text: &str) -> Result<Vec<(String, String)>, SgSpriteErr> {
+    let map: HashMap<String, String> =
+        serde_json::from_str(text).map_err(|e| SgSpriteErr::BadPairing(fmt!("manifest: {}", e)))?;
+    Ok(map.into_iter().collect())
+}
+
+#[cfg(not(feature = "json"))]
+fn manifest_json(_text: &str) -> Result<Vec<(String, String)>, SgSpriteErr> {
+    raise!(SgSpriteErr::BadPairing("JSON manifest requires json feature".to_string()))
+}
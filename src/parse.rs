@@ -1,19 +1,30 @@
+//! `.lay` file parser. See `lay-format.md` for the format description.
+
 use super::*;
-use byteorder::{LittleEndian, ReadBytesExt};
-use libflate::zlib;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use libflate::{deflate, gzip, zlib};
+use log::debug;
+use std::fmt::{self, Display, Formatter};
 use std::collections::HashMap;
 use std::format as fmt;
 use std::fs::File;
 use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::str::FromStr;
 
 const COMMON_BUF_SZ: usize = 32;
-const HEADER_SZ: usize = 4 * 2;     // [u32:sprite_c][u32:chunk_c]
-const SPRITE_SZ: usize = 4 * 3;     // [32][u32:chunk_offset][u32:chunk_count]
-const CHUNK_SZ: usize = 4 * 4;      // [f32:img_x][f32:img_y][f32:chunk_x][f32:chunk_y]
-const SPRITE_SIZE_PAD: i32 = 32;    // dangling block
-const SPRITES_MAX_RAW: u32 = 65536; // for compressed lay detection
+pub(crate) const HEADER_SZ: usize = 4 * 2; // [u32:sprite_c][u32:chunk_c]
+pub(crate) const SPRITE_SZ: usize = 4 * 3; // [32][u32:chunk_offset][u32:chunk_count]
+pub(crate) const CHUNK_SZ: usize = 4 * 4;  // [f32:img_x][f32:img_y][f32:chunk_x][f32:chunk_y]
+const SPRITES_MAX_RAW: u32 = 65536; // default limits, also the least ones raw lay detection allows
+const CHUNKS_MAX_RAW: u32 = 1 << 20;
+
+/// Block size used when it can't be detected from chunk coordinates
+pub const DEFAULT_BLOCK_SIZE: u32 = 32;
 
-#[derive(PartialEq, Debug)]
+/// Sprite type, stored in the `D` byte of sprite info
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum SpriteT {
     Base,                    // 0x00 Base sprite / layer 0
     Sub,                     // 0x20 Sub sprite (implicitly depends on Base)
@@ -22,119 +33,535 @@ pub enum SpriteT {
         depends_on: u8
     },
     Overlay,                 // 0x50 Transparent overlay
+    Unknown(u8),             // Any other type byte, see UnknownTypePolicy
 }
 
-#[derive(Debug)]
+impl SpriteT {
+    /// Decode sprite type from info bytes `[A][B][C][D]`
+    pub fn from_info(info: [u8; 4]) -> SpriteT {
+        let type_id = info[3];
+        match type_id {
+            0x00 => SpriteT::Base,
+            0x20 => SpriteT::Sub,
+            0x40 | 0x30 | 0x60 => SpriteT::Dep { exact_type: type_id, depends_on: info[1] },
+            0x50 => SpriteT::Overlay,
+            _ => SpriteT::Unknown(type_id),
+        }
+    }
+
+    /// Info bytes `[A][B][C][D]` the way they usually look for this type
+    pub fn info(self, id: u8) -> [u8; 4] {
+        match self {
+            SpriteT::Base => [id, 0x00, 0x00, 0x00],
+            SpriteT::Sub => [id, 0x00, 0x00, 0x20],
+            SpriteT::Dep { exact_type, depends_on } => [id, depends_on, 0x00, exact_type],
+            SpriteT::Overlay => [id, 0x00, 0x10, 0x50],
+            SpriteT::Unknown(type_id) => [id, 0x00, 0x00, type_id],
+        }
+    }
+}
+
+/// Sprite variant entry
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sprite {
     pub sprite_type: SpriteT,
+    /// Sprite id (`A` byte)
     pub id: u8,
+    /// Original info bytes `[A][B][C][D]`, written back as [`Sprite::info_bytes`]
+    pub info: [u8; 4],
+    /// Index of the first chunk of this sprite in [`ParsedLay::chunks`]
     pub chunk_offset: usize,
     pub chunk_count: usize,
 }
 
-#[derive(Debug)]
+impl Sprite {
+    pub fn new(sprite_type: SpriteT, id: u8, chunk_offset: usize, chunk_count: usize) -> Self {
+        Sprite { sprite_type, id, info: sprite_type.info(id), chunk_offset, chunk_count }
+    }
+
+    /// Info bytes to write: the original ones with `A` set to `id`,
+    /// or the usual ones for `sprite_type` if it no longer matches them
+    pub fn info_bytes(&self) -> [u8; 4] {
+        if SpriteT::from_info(self.info) != self.sprite_type {
+            return self.sprite_type.info(self.id);
+        }
+
+        let mut info = self.info;
+        info[0] = self.id;
+        info
+    }
+}
+
+/// Chunk entry, coordinates are converted from f32 as is
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Chunk {
+    /// Position on the target sprite, relative to the screen center
     pub img_x: i32,
     pub img_y: i32,
+    /// Position in the source png, points to (1,1) pixel of the block
     pub chunk_x: i32,
     pub chunk_y: i32,
 }
 
+/// Lay file compression
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Compression {
+    Raw,
+    /// zlib stream, used in Chaos;Child
+    Zlib,
+    /// Deflate stream without zlib header
+    Deflate,
+    Gzip,
+}
+
+/// Format anomaly that doesn't prevent parsing
+#[derive(PartialEq, Eq, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LayWarning {
+    /// Overlay info bytes `B, C` aren't `00 10`
+    AmbiguousOverlayHead { sprite: usize, head: [u8; 4] },
+    /// Info byte `C` isn't zero
+    AmbiguousSpriteHead { sprite: usize, head: [u8; 4] },
+    /// Sprite of unknown type was skipped or kept as standalone, according to [`UnknownTypePolicy`].
+    /// `sprite` is the index in the file, since skipped sprites aren't in [`ParsedLay::sprites`]
+    UnknownSpriteType { sprite: usize, type_id: u8, skipped: bool },
+    /// Block size measured from chunk positions isn't a power of two of at least 8 pixels
+    /// or doesn't fit the source spacing, [`DEFAULT_BLOCK_SIZE`] is used instead
+    UnlikelyBlockSize { detected: u32 },
+}
+
+impl Display for LayWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LayWarning::AmbiguousOverlayHead { sprite, head } => {
+                write!(f, "Ambiguous overlay head [1..3]: {:#X} (sprite {})", Hex(&head[1..3]), sprite)
+            }
+            LayWarning::AmbiguousSpriteHead { sprite, head } => {
+                write!(f, "Ambiguous sprite head [2]: {:#X} (sprite {})", Hex(&head[2..3]), sprite)
+            }
+            LayWarning::UnknownSpriteType { sprite, type_id, skipped } => {
+                let action = if *skipped { "skipped" } else { "kept as standalone" };
+                write!(f, "Unknown sprite type {:#04X}, {} (sprite {})", type_id, action, sprite)
+            }
+            LayWarning::UnlikelyBlockSize { detected } => {
+                write!(f, "Unlikely block size {}, using {}", detected, DEFAULT_BLOCK_SIZE)
+            }
+        }
+    }
+}
+
+/// What to do with sprites of unknown type
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum UnknownTypePolicy {
+    /// Fail the whole file with [`SgSpriteErr::UnknownSpriteType`]
+    #[default]
+    Error,
+    /// Drop the sprite, its chunks are left unused
+    Skip,
+    /// Keep the sprite as [`SpriteT::Unknown`], drawn alone without dependencies
+    Standalone,
+}
+
+impl FromStr for UnknownTypePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(UnknownTypePolicy::Error),
+            "skip" => Ok(UnknownTypePolicy::Skip),
+            "standalone" => Ok(UnknownTypePolicy::Standalone),
+            _ => Err(fmt!("unknown policy {}, expected error, skip or standalone", s)),
+        }
+    }
+}
+
+/// Byte order of lay fields
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Endian {
+    #[default]
+    Little,
+    /// PS3 and Xbox 360 releases
+    Big,
+}
+
+impl Endian {
+    pub fn read_u32(self, b: &[u8]) -> u32 {
+        match self {
+            Endian::Little => LittleEndian::read_u32(b),
+            Endian::Big => BigEndian::read_u32(b),
+        }
+    }
+
+    pub fn read_f32(self, b: &[u8]) -> f32 {
+        match self {
+            Endian::Little => LittleEndian::read_f32(b),
+            Endian::Big => BigEndian::read_f32(b),
+        }
+    }
+}
+
+impl FromStr for Endian {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "little" => Ok(Endian::Little),
+            "big" => Ok(Endian::Big),
+            _ => Err(fmt!("unknown byte order {}, expected little or big", s)),
+        }
+    }
+}
+
+/// Parser options, [`parse_lay`] and friends use the defaults
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ParseOpts {
+    pub unknown_types: UnknownTypePolicy,
+    /// Side of the square chunk block in pixels, detected from chunk coordinates if None
+    pub block_size: Option<u32>,
+    /// Byte order of the (decompressed) file, detected from the header if None
+    pub endian: Option<Endian>,
+    /// Compression of the file, detected from the header if None
+    pub compression: Option<Compression>,
+    pub limits: Limits,
+}
+
+/// Parser limits against malformed or hostile files, exceeding any of them fails the file
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub sprites: u32,
+    pub chunks: u32,
+    /// Decompressed file size in bytes, including trailing data
+    pub decompressed: u64,
+    /// Canvas width and height in pixels
+    pub canvas: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            sprites: SPRITES_MAX_RAW,
+            chunks: CHUNKS_MAX_RAW,
+            decompressed: 64 << 20,
+            canvas: 16384,
+        }
+    }
+}
+
+/// Parsed `.lay` file
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ParsedLay {
     pub sprites: Vec<Sprite>,
+    /// Sub sprite id -> index in `sprites`
     pub sub_map: HashMap<u8, usize>,
     pub chunks: Vec<Chunk>,
+    /// Index of the Base sprite, if the first sprite is Base
     pub base_dep: Option<usize>,
+    /// Full size of the composed sprite
     pub sprite_w: u32,
     pub sprite_h: u32,
     pub sprite_xy_min: (i32, i32),
     pub sprite_xy_max: (i32, i32),
+    /// Side of the square chunk block in pixels
+    pub block_size: u32,
+    /// Byte order of the source file
+    pub endian: Endian,
+    pub warnings: Vec<LayWarning>,
+    /// Compression of the source file
+    pub compression: Compression,
+    /// Data after the chunk list
+    pub trailing: Vec<u8>,
 }
 
 #[inline]
-fn read_u32_le(src: &mut impl Read) -> io::Result<u32> {
-    src.read_u32::<LittleEndian>()
+fn read_f32_to_i32<B: ByteOrder>(src: &mut impl Read, offset: u64) -> Result<i32, SgSpriteErr> {
+    let f = src.read_f32::<B>()?;
+    if f.is_nan() || f.is_infinite() || f.fract() != 0f32 {
+        raise!(SgSpriteErr::BadCoord { value: f, offset })
+    }
+    Ok(f as i32)
 }
 
 #[inline]
-fn read_f32_le_to_i32(src: &mut impl Read) -> Result<i32, SgSpriteErr> {
-    let f = src.read_f32::<LittleEndian>()?;
-    if f.is_nan() || f.is_infinite() {
-        raise!("unsuitable f32 {}", f)
+fn header_err(e: io::Error) -> SgSpriteErr {
+    SgSpriteErr::read(e, LaySection::Header, 0)
+}
+
+impl ParseOpts {
+    /// Parse a `.lay` file, raw or compressed, starting at the current position of `lay_file`
+    pub fn parse_lay<R: Read + Seek>(&self, lay_file: &mut R) -> Result<ParsedLay, SgSpriteErr> {
+        let start = lay_file.stream_position()?;
+        let mut head = [0u8; HEADER_SZ];
+        lay_file.read_exact(&mut head).map_err(header_err)?;
+        let len = lay_file.seek(SeekFrom::End(0))? - start;
+        lay_file.seek(SeekFrom::Start(start))?;
+
+        self.parse_detect(&head, Some(len), BufReader::new(lay_file))
     }
-    if f.fract() != 0f32 {
-        raise!("f32 has fract part {}", f)
+
+    /// Same as [`ParseOpts::parse_lay`] for non-seekable readers.
+    /// Files that look both like zlib and raw lay are read into memory to tell them apart by size
+    pub fn parse_lay_read(&self, mut lay: impl Read) -> Result<ParsedLay, SgSpriteErr> {
+        let mut head = [0u8; HEADER_SZ];
+        lay.read_exact(&mut head).map_err(header_err)?;
+        let lay = (&head[..]).chain(lay);
+
+        let ambiguous = self.detect_compression(&head) == Compression::Zlib && self.raw_size(&head).is_some();
+        if self.compression.is_none() && ambiguous {
+            let mut buf = Vec::new();
+            lay.take(self.limits.decompressed.saturating_add(1)).read_to_end(&mut buf)?;
+            return self.parse_lay_bytes(&buf);
+        }
+
+        self.parse_detect(&head, None, BufReader::new(lay))
     }
-    Ok(f as i32)
+
+    /// Same as [`ParseOpts::parse_lay`] for in-memory lay data
+    pub fn parse_lay_bytes(&self, lay: &[u8]) -> Result<ParsedLay, SgSpriteErr> {
+        let head = lay
+            .get(..HEADER_SZ)
+            .ok_or(SgSpriteErr::Truncated { section: LaySection::Header, offset: 0 })?;
+
+        self.parse_detect(head, Some(lay.len() as u64), lay)
+    }
+
+    /// Decompress the whole lay file, compression is detected if not set
+    pub fn decompress_lay(&self, data: &[u8]) -> Result<(Vec<u8>, Compression), SgSpriteErr> {
+        let head = data
+            .get(..HEADER_SZ)
+            .ok_or(SgSpriteErr::Truncated { section: LaySection::Header, offset: 0 })?;
+
+        let compression = self.compression.unwrap_or_else(|| self.detect_compression_len(head, data.len() as u64));
+        let limit = self.limits.decompressed;
+        let mut buf = Vec::with_capacity(data.len().min(limit as usize));
+
+        let mut bf = decoder(compression, data)?.take(limit.saturating_add(1));
+        bf.read_to_end(&mut buf).map_err(|e| match compression {
+            Compression::Deflate => SgSpriteErr::UnknownCompression,
+            _ => SgSpriteErr::Io(e),
+        })?;
+
+        if buf.len() as u64 > limit {
+            raise!(SgSpriteErr::TooLarge { limit });
+        }
+        Ok((buf, compression))
+    }
+
+    /// `len` is the size of the file, if known
+    fn parse_detect(&self, head: &[u8], len: Option<u64>, src: impl Read) -> Result<ParsedLay, SgSpriteErr> {
+        let compression = self.compression.unwrap_or_else(|| match len {
+            Some(len) => self.detect_compression_len(head, len),
+            None => self.detect_compression(head),
+        });
+        debug!("{:?} lay", compression);
+
+        // one byte over the limit is enough to tell it's exceeded
+        let limit = self.limits.decompressed;
+        let mut bf = decoder(compression, src)?.take(limit.saturating_add(1));
+        let res = parse_lay_impl(&mut bf, self);
+        if bf.limit() == 0 {
+            raise!(SgSpriteErr::TooLarge { limit });
+        }
+
+        let mut lay = res.map_err(|e| match e {
+            // deflate is a last resort, so broken stream means unknown format
+            SgSpriteErr::BadHeader(_) | SgSpriteErr::Truncated { section: LaySection::Header, .. }
+                if compression == Compression::Deflate =>
+            {
+                SgSpriteErr::UnknownCompression
+            }
+            e => e,
+        })?;
+
+        lay.compression = compression;
+        Ok(lay)
+    }
+
+    /// Detect compression by the first 8 bytes of the file.
+    /// Files that look neither like gzip, zlib nor raw lay are assumed to be raw deflate
+    pub fn detect_compression(&self, head: &[u8]) -> Compression {
+        match *head {
+            [0x1F, 0x8B, ..] => Compression::Gzip,
+            [cmf, flg, ..] if is_zlib_header(cmf, flg) => Compression::Zlib,
+            _ if self.detect_endian(head).is_some() => Compression::Raw,
+            _ => Compression::Deflate,
+        }
+    }
+
+    /// Same as [`ParseOpts::detect_compression`], knowing the file size `len`. Little-endian raw lays
+    /// with 376 (`78 01`) or 1384 (`78 05`) sprites, among others, have zlib-like header,
+    /// such files are raw if sprite and chunk lists fit into the file
+    pub fn detect_compression_len(&self, head: &[u8], len: u64) -> Compression {
+        match self.detect_compression(head) {
+            Compression::Zlib if matches!(self.raw_size(head), Some(sz) if sz <= len) => Compression::Raw,
+            c => c,
+        }
+    }
+
+    /// Detect byte order by the first 8 bytes of the decompressed file: the order in which
+    /// sprite and chunk counts look sane. Little-endian wins if both do, None if neither does.
+    /// Limits raised over the defaults make larger counts sane, lowered ones are checked by the parser
+    pub fn detect_endian(&self, head: &[u8]) -> Option<Endian> {
+        if head.len() < HEADER_SZ {
+            return None;
+        }
+
+        let sprites_max = self.limits.sprites.max(SPRITES_MAX_RAW);
+        let chunks_max = self.limits.chunks.max(CHUNKS_MAX_RAW);
+        let sane = |e: Endian| {
+            let (sprite_count, chunk_count) = (e.read_u32(head), e.read_u32(&head[4..]));
+            (1..=sprites_max).contains(&sprite_count) && chunk_count <= chunks_max
+        };
+
+        [Endian::Little, Endian::Big].iter().copied().find(|e| sane(*e))
+    }
+
+    /// Size of the header, sprite and chunk lists of a raw lay, None if the header doesn't look raw
+    fn raw_size(&self, head: &[u8]) -> Option<u64> {
+        let e = self.detect_endian(head)?;
+        let (sprite_count, chunk_count) = (e.read_u32(head) as u64, e.read_u32(&head[4..]) as u64);
+        Some(HEADER_SZ as u64 + sprite_count * SPRITE_SZ as u64 + chunk_count * CHUNK_SZ as u64)
+    }
+}
+
+/// Parse a `.lay` file, raw or compressed, starting at the current position of `lay_file`
+pub fn parse_lay<R: Read + Seek>(lay_file: &mut R) -> Result<ParsedLay, SgSpriteErr> {
+    ParseOpts::default().parse_lay(lay_file)
+}
+
+/// Same as [`parse_lay`] for non-seekable readers
+pub fn parse_lay_read(lay: impl Read) -> Result<ParsedLay, SgSpriteErr> {
+    ParseOpts::default().parse_lay_read(lay)
+}
+
+/// Same as [`parse_lay`] for in-memory lay data
+pub fn parse_lay_bytes(lay: &[u8]) -> Result<ParsedLay, SgSpriteErr> {
+    ParseOpts::default().parse_lay_bytes(lay)
+}
+
+/// Detect compression by the first 8 bytes of the file, within the default limits
+pub fn detect_compression(head: &[u8]) -> Compression {
+    ParseOpts::default().detect_compression(head)
+}
+
+/// Same as [`detect_compression`], knowing the file size `len`
+pub fn detect_compression_len(head: &[u8], len: u64) -> Compression {
+    ParseOpts::default().detect_compression_len(head, len)
+}
+
+/// Detect byte order by the first 8 bytes of the decompressed file, within the default limits
+pub fn detect_endian(head: &[u8]) -> Option<Endian> {
+    ParseOpts::default().detect_endian(head)
+}
+
+fn is_zlib_header(cmf: u8, flg: u8) -> bool {
+    let method = cmf & 0x0F; // 8 - deflate
+    let window = cmf >> 4;   // log2(window size) - 8, 7 at most
+    let check = (cmf as u16) << 8 | flg as u16;
+    let dict = flg & 0x20 != 0;
+
+    method == 8 && window <= 7 && check.is_multiple_of(31) && !dict
+}
+
+fn decoder<'a>(compression: Compression, src: impl Read + 'a) -> Result<Box<dyn Read + 'a>, SgSpriteErr> {
+    Ok(match compression {
+        Compression::Raw => Box::new(src),
+        Compression::Zlib => Box::new(zlib::Decoder::new(src).map_err(SgSpriteErr::BadHeader)?),
+        Compression::Gzip => Box::new(gzip::Decoder::new(src).map_err(SgSpriteErr::BadHeader)?),
+        Compression::Deflate => Box::new(deflate::Decoder::new(src)),
+    })
+}
+
+/// Detect compression and decompress the whole lay file
+pub fn decompress_lay(data: &[u8]) -> Result<(Vec<u8>, Compression), SgSpriteErr> {
+    ParseOpts::default().decompress_lay(data)
 }
 
-pub fn parse_lay(lay_file: &mut File) -> Result<ParsedLay, SgSpriteErr> {
-    let pre_read = read_u32_le(lay_file)?;
-    lay_file.seek(SeekFrom::Start(0))?;
+fn parse_lay_impl(mut bf: impl Read, opts: &ParseOpts) -> Result<ParsedLay, SgSpriteErr> {
+    let mut head = [0u8; HEADER_SZ];
+    bf.read_exact(&mut head).map_err(header_err)?;
+
+    let endian = opts.endian.or_else(|| opts.detect_endian(&head)).unwrap_or_default();
+    debug!("{:?} endian", endian);
 
-    if pre_read > SPRITES_MAX_RAW {
-        eprintln!("[I] Compressed lay");
-        let buf_pre = BufReader::new(lay_file);
-        let z = zlib::Decoder::new(buf_pre)?;
-        parse_lay_impl(z)
-    } else {
-        eprintln!("[I] Raw lay");
-        let buf = BufReader::new(lay_file);
-        parse_lay_impl(buf)
+    match endian {
+        Endian::Little => parse_lay_body::<LittleEndian>(endian, &head, bf, opts),
+        Endian::Big => parse_lay_body::<BigEndian>(endian, &head, bf, opts),
     }
 }
 
-fn parse_lay_impl(mut bf: impl Read) -> Result<ParsedLay, SgSpriteErr> {
+/// Everything after the header, `B` must match `endian`
+fn parse_lay_body<B: ByteOrder>(
+    endian: Endian,
+    head: &[u8],
+    mut bf: impl Read,
+    opts: &ParseOpts,
+) -> Result<ParsedLay, SgSpriteErr> {
     let mut c_buf = [0u8; COMMON_BUF_SZ];
 
-    let sprite_count: u32;
-    let chunk_count: u32;
-    {
-        // read header
-        let buf = &mut c_buf[..HEADER_SZ];
-        bf.read_exact(buf)?;
+    let sprite_count = B::read_u32(head);
+    let chunk_count = B::read_u32(&head[4..]);
 
-        let buf = &mut &*buf;
-        sprite_count = read_u32_le(buf)?;
-        chunk_count = read_u32_le(buf)?;
+    // counts are checked before anything is allocated for them
+    let limits = &opts.limits;
+    if sprite_count > limits.sprites {
+        raise!(SgSpriteErr::TooManySprites { count: sprite_count, limit: limits.sprites });
+    }
+    if chunk_count > limits.chunks {
+        raise!(SgSpriteErr::TooManyChunks { count: chunk_count, limit: limits.chunks });
     }
 
     let mut sprites: Vec<Sprite> = Vec::with_capacity(sprite_count as usize);
     let mut sub_map: HashMap<u8, usize> = HashMap::new();
+    let mut warnings = Vec::new();
 
     // read sprites
-    for _i in 0..sprite_count {
+    for i in 0..sprite_count as u64 {
+        let offset = HEADER_SZ as u64 + i * SPRITE_SZ as u64;
         let buf = &mut c_buf[..SPRITE_SZ];
-        bf.read_exact(buf)?;
+        bf.read_exact(buf).map_err(|e| SgSpriteErr::read(e, LaySection::Sprites, offset))?;
 
         let buf = &mut &*buf;
-        let mut head = [0u8; 4];
-        buf.read_exact(&mut head)?;
+        // info is a u32 with A in the lowest byte, so it's reversed in big-endian files
+        let head = buf.read_u32::<B>()?.to_le_bytes();
 
-        let type_id = head[3];
         let s = Sprite {
-            sprite_type: match type_id {
-                0x00 => SpriteT::Base,
-                0x20 => SpriteT::Sub,
-                0x40 | 0x30 | 0x60 => SpriteT::Dep { exact_type: type_id, depends_on: head[1] },
-                0x50 => SpriteT::Overlay,
-                _ => raise!("Unknown sprite type {:#X}", Hex(&head[3..4])),
-            },
+            sprite_type: SpriteT::from_info(head),
             id: head[0],
-            chunk_offset: read_u32_le(buf)? as usize,
-            chunk_count: read_u32_le(buf)? as usize,
+            info: head,
+            chunk_offset: buf.read_u32::<B>()? as usize,
+            chunk_count: buf.read_u32::<B>()? as usize,
         };
 
         // format warnings & insert dependency
+        let sprite = sprites.len();
+        if let SpriteT::Unknown(type_id) = s.sprite_type {
+            let skipped = match opts.unknown_types {
+                UnknownTypePolicy::Error => raise!(SgSpriteErr::UnknownSpriteType { type_id, offset }),
+                UnknownTypePolicy::Skip => true,
+                UnknownTypePolicy::Standalone => false,
+            };
+
+            warnings.push(LayWarning::UnknownSpriteType { sprite: i as usize, type_id, skipped });
+            if skipped {
+                continue;
+            }
+        }
+
         match s.sprite_type {
             SpriteT::Sub => {
-                sub_map.insert(s.id, sprites.len());
+                sub_map.insert(s.id, sprite);
             }
             SpriteT::Overlay => if head[1] != 0 || head[2] != 16 {
-                eprintln!("[W] Ambiguous overlay head [1..3]: {:#X}", Hex(&head[1..3]));
+                warnings.push(LayWarning::AmbiguousOverlayHead { sprite, head });
             }
+            SpriteT::Unknown(_) => (),
             _ => if head[2] != 0 {
-                eprintln!("[W] Ambiguous sprite head [2]: {:#X}", Hex(&head[2..3]));
+                warnings.push(LayWarning::AmbiguousSpriteHead { sprite, head });
             }
         }
 
@@ -142,7 +569,7 @@ fn parse_lay_impl(mut bf: impl Read) -> Result<ParsedLay, SgSpriteErr> {
     }
 
     if sprites.is_empty() {
-        raise!("no sprites");
+        raise!(SgSpriteErr::NoSprites);
     }
 
     // if the base is absent - don't depend subs on anything
@@ -158,14 +585,16 @@ fn parse_lay_impl(mut bf: impl Read) -> Result<ParsedLay, SgSpriteErr> {
     let mut sprite_min_y: i32 = 0;
 
     // read chunks
-    for _i in 0..chunk_count {
+    let chunks_start = HEADER_SZ as u64 + sprite_count as u64 * SPRITE_SZ as u64;
+    for i in 0..chunk_count as u64 {
+        let offset = chunks_start + i * CHUNK_SZ as u64;
         let buf = &mut c_buf[..CHUNK_SZ];
-        bf.read_exact(buf)?;
+        bf.read_exact(buf).map_err(|e| SgSpriteErr::read(e, LaySection::Chunks, offset))?;
 
         let buf = &mut &*buf;
         let mut chu = [0i32; CHUNK_SZ / 4];
-        for c in &mut chu {
-            *c = read_f32_le_to_i32(buf)?;
+        for (j, c) in chu.iter_mut().enumerate() {
+            *c = read_f32_to_i32::<B>(buf, offset + j as u64 * 4)?;
         }
 
         let (img_x, img_y) = (chu[0], chu[1]);
@@ -184,8 +613,28 @@ fn parse_lay_impl(mut bf: impl Read) -> Result<ParsedLay, SgSpriteErr> {
         chunks.push(s);
     }
 
-    let sprite_w = sprite_max_x + sprite_min_x.abs() + SPRITE_SIZE_PAD;
-    let sprite_h = sprite_max_y + sprite_min_y.abs() + SPRITE_SIZE_PAD;
+    let mut trailing = Vec::new();
+    bf.read_to_end(&mut trailing)?;
+
+    let block_size = match opts.block_size {
+        Some(b) => b,
+        None => match detect_block_size(&sprites, &chunks) {
+            Some(b) if b >= 8 && b.is_power_of_two() && src_spacing(&chunks).is_multiple_of(b) => b,
+            Some(b) => {
+                warnings.push(LayWarning::UnlikelyBlockSize { detected: b });
+                DEFAULT_BLOCK_SIZE
+            }
+            None => DEFAULT_BLOCK_SIZE,
+        },
+    };
+    debug!("block size {}", block_size);
+
+    // dangling block
+    let sprite_w = sprite_max_x as i64 + sprite_min_x.unsigned_abs() as i64 + block_size as i64;
+    let sprite_h = sprite_max_y as i64 + sprite_min_y.unsigned_abs() as i64 + block_size as i64;
+    if sprite_w > limits.canvas as i64 || sprite_h > limits.canvas as i64 {
+        raise!(SgSpriteErr::CanvasTooLarge { w: sprite_w, h: sprite_h, limit: limits.canvas });
+    }
 
     let res = ParsedLay {
         chunks,
@@ -196,7 +645,51 @@ fn parse_lay_impl(mut bf: impl Read) -> Result<ParsedLay, SgSpriteErr> {
         sprite_h: sprite_h as u32,
         sprite_xy_min: (sprite_min_x, sprite_min_y),
         sprite_xy_max: (sprite_max_x, sprite_max_y),
+        block_size,
+        endian,
+        warnings,
+        compression: Compression::Raw,
+        trailing,
     };
 
     Ok(res)
 }
+
+/// Greatest common divisor of distances between chunks of the same sprite,
+/// None if no sprite has chunks at different positions
+fn detect_block_size(sprites: &[Sprite], chunks: &[Chunk]) -> Option<u32> {
+    let mut res = 0;
+    for s in sprites {
+        let range = s.chunk_offset.checked_add(s.chunk_count).map(|end| s.chunk_offset..end);
+        let sprite_chunks = match range.and_then(|r| chunks.get(r)) {
+            Some(c) => c,
+            None => continue, // reported by validate_lay
+        };
+
+        if let Some(first) = sprite_chunks.first() {
+            for c in sprite_chunks {
+                res = gcd(res, (c.img_x as i64 - first.img_x as i64).unsigned_abs() as u32);
+                res = gcd(res, (c.img_y as i64 - first.img_y as i64).unsigned_abs() as u32);
+            }
+        }
+    }
+
+    if res > 0 { Some(res) } else { None }
+}
+
+/// Greatest common divisor of distances between chunks in the source image, 0 if all are the same
+fn src_spacing(chunks: &[Chunk]) -> u32 {
+    let first = match chunks.first() {
+        Some(c) => c,
+        None => return 0,
+    };
+
+    chunks.iter().fold(0, |res, c| {
+        let res = gcd(res, (c.chunk_x as i64 - first.chunk_x as i64).unsigned_abs() as u32);
+        gcd(res, (c.chunk_y as i64 - first.chunk_y as i64).unsigned_abs() as u32)
+    })
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
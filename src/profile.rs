@@ -0,0 +1,111 @@
+//! Per-game presets of lay file quirks.
+//!
+//! A profile is selected with `--game` or detected from a parsed lay. Compression and byte order
+//! are recognized by the parser anyway, they tell a game apart only if no other game shares them:
+//! zlib-compressed lays are Chaos;Child, big-endian ones are Steins;Gate 0 PS3 release.
+
+use super::*;
+
+/// Lay file quirks of a single game release
+#[derive(Debug)]
+pub struct GameProfile {
+    /// Name used with `--game`
+    pub id: &'static str,
+    pub title: &'static str,
+    pub compression: Compression,
+    pub endian: Endian,
+    /// Source image naming, in order of preference
+    pub pair_rules: &'static [PairRule],
+    /// Screen size in pixels, chunk positions are relative to the screen center
+    pub screen: (u32, u32),
+}
+
+/// Known profiles, one per game release
+pub const PROFILES: &[GameProfile] = &[
+    GameProfile {
+        id: "sg",
+        title: "Steins;Gate Steam Edition",
+        compression: Compression::Raw,
+        endian: Endian::Little,
+        pair_rules: &[PairRule::Exact],
+        screen: (1280, 720),
+    },
+    GameProfile {
+        id: "sg-hiyoku",
+        title: "Steins;Gate My Darling's Embrace",
+        compression: Compression::Raw,
+        endian: Endian::Little,
+        pair_rules: &[PairRule::Exact],
+        screen: (1280, 720),
+    },
+    GameProfile {
+        id: "sg-lbp",
+        title: "Steins;Gate Linear Bounded Phenogram",
+        compression: Compression::Raw,
+        endian: Endian::Little,
+        pair_rules: &[PairRule::Exact],
+        screen: (1920, 1080),
+    },
+    GameProfile {
+        id: "sg0",
+        title: "Steins;Gate 0",
+        compression: Compression::Raw,
+        endian: Endian::Little,
+        pair_rules: &[PairRule::Exact, PairRule::Underscore],
+        screen: (1920, 1080),
+    },
+    GameProfile {
+        id: "sg0-ps3",
+        title: "Steins;Gate 0 (PS3)",
+        compression: Compression::Raw,
+        endian: Endian::Big,
+        pair_rules: &[PairRule::Exact, PairRule::Underscore],
+        screen: (1280, 720),
+    },
+    GameProfile {
+        id: "cc",
+        title: "Chaos;Child",
+        compression: Compression::Zlib,
+        endian: Endian::Little,
+        pair_rules: &[PairRule::Exact],
+        screen: (1920, 1080),
+    },
+    GameProfile {
+        id: "oregairu",
+        title: "Yahari Game Demo Ore no Seishun Love-Kome wa Machigatteiru. Zoku",
+        compression: Compression::Raw,
+        endian: Endian::Little,
+        // Vita release, sources are usually GXTs converted by Scarlet
+        pair_rules: &[PairRule::ImageSuffix, PairRule::Exact],
+        screen: (960, 544),
+    },
+];
+
+impl GameProfile {
+    /// Profile by its `--game` name
+    pub fn by_id(id: &str) -> Result<&'static GameProfile, String> {
+        PROFILES.iter().find(|p| p.id == id).ok_or_else(|| {
+            let ids: Vec<_> = PROFILES.iter().map(|p| p.id).collect();
+            fmt!("unknown game {}, expected one of: {}", id, ids.join(", "))
+        })
+    }
+
+    /// Parser options with this profile's quirks, fields set in `opts` are kept
+    pub fn parse_opts(&self, opts: ParseOpts) -> ParseOpts {
+        ParseOpts {
+            compression: opts.compression.or(Some(self.compression)),
+            endian: opts.endian.or(Some(self.endian)),
+            ..opts
+        }
+    }
+}
+
+/// The only profile with the compression and byte order of the parsed lay,
+/// None if there are none or several of them
+pub fn detect_profile(lay: &ParsedLay) -> Option<&'static GameProfile> {
+    let mut found = PROFILES.iter().filter(|p| p.compression == lay.compression && p.endian == lay.endian);
+    match (found.next(), found.next()) {
+        (Some(p), None) => Some(p),
+        _ => None,
+    }
+}
@@ -0,0 +1,191 @@
+//! PVRTC1 (2 and 4 bpp) decoder.
+//!
+//! Each 64-bit block is `[u32:modulation][u32:colors]`, blocks are Morton-ordered.
+//! Every block stores two colors, A and B. Pixel color is a blend of A and B, both
+//! bilinearly interpolated between the four nearest block centers, by the pixel's
+//! modulation value (out of 8).
+
+use super::*;
+use crate::swizzle::morton_pos;
+use byteorder::{ByteOrder, LittleEndian};
+use image::{Rgba, RgbaImage};
+
+const BLOCK_SZ: usize = 8;
+const BLOCK_H: usize = 4;
+const MOD_VALUES: [u32; 4] = [0, 3, 5, 8];
+
+/// Block colors, RGB in 5 bits and alpha in 4 bits
+#[derive(Clone, Copy, Default)]
+struct BlockColors {
+    a: [u32; 4],
+    b: [u32; 4],
+}
+
+fn bits5(v: u32, from: u32) -> u32 {
+    match from {
+        3 => v << 2 | v >> 1,
+        4 => v << 1 | v >> 3,
+        _ => v,
+    }
+}
+
+fn color_a(c: u32) -> [u32; 4] {
+    if c & 0x8000 != 0 {
+        [c >> 10 & 0x1F, c >> 5 & 0x1F, bits5(c >> 1 & 0xF, 4), 0xF]
+    } else {
+        [bits5(c >> 8 & 0xF, 4), bits5(c >> 4 & 0xF, 4), bits5(c >> 1 & 0x7, 3), (c >> 12 & 0x7) << 1]
+    }
+}
+
+fn color_b(c: u32) -> [u32; 4] {
+    let c = c >> 16;
+    if c & 0x8000 != 0 {
+        [c >> 10 & 0x1F, c >> 5 & 0x1F, c & 0x1F, 0xF]
+    } else {
+        [bits5(c >> 8 & 0xF, 4), bits5(c >> 4 & 0xF, 4), bits5(c & 0xF, 4), (c >> 12 & 0x7) << 1]
+    }
+}
+
+/// Size of `w x h` PVRTC data, dimensions are rounded up to powers of two and at least 2 blocks
+pub(crate) fn data_size(w: u32, h: u32, bpp2: bool) -> usize {
+    let (bx, by) = block_dims(w, h, bpp2);
+    bx * by * BLOCK_SZ
+}
+
+fn block_dims(w: u32, h: u32, bpp2: bool) -> (usize, usize) {
+    let block_w = if bpp2 { 8 } else { 4 };
+    let (pw, ph) = (w.next_power_of_two() as usize, h.next_power_of_two() as usize);
+    ((pw / block_w).max(2), (ph / BLOCK_H).max(2))
+}
+
+pub(crate) fn decode_pvrtc(data: &[u8], w: u32, h: u32, bpp2: bool) -> Result<RgbaImage, SgSpriteErr> {
+    let need = data_size(w, h, bpp2);
+    if data.len() < need {
+        raise!(SgSpriteErr::BadTexture(fmt!("PVRTC data is {} bytes, {} expected", data.len(), need)));
+    }
+
+    let block_w = if bpp2 { 8 } else { 4 };
+    let (bx_n, by_n) = block_dims(w, h, bpp2);
+    let (gw, gh) = (bx_n * block_w, by_n * BLOCK_H);
+
+    let mut colors = vec![BlockColors::default(); bx_n * by_n];
+    // modulation values out of 8, or raw 2-bit values while 2bpp interpolation is pending
+    let mut modulation = vec![0u32; gw * gh];
+    let mut interpolate = vec![None; gw * gh];
+    // 4bpp pixels with zero alpha
+    let mut punch = vec![false; gw * gh];
+
+    for (i, block) in data[..need].chunks_exact(BLOCK_SZ).enumerate() {
+        let (bx, by) = morton_pos(i, bx_n, by_n);
+        let mods = LittleEndian::read_u32(block);
+        let c = LittleEndian::read_u32(&block[4..]);
+        let mode = c & 1 != 0;
+
+        colors[by * bx_n + bx] = BlockColors { a: color_a(c), b: color_b(c) };
+
+        let origin = by * BLOCK_H * gw + bx * block_w;
+        let at = |x: usize, y: usize| origin + y * gw + x;
+
+        match (bpp2, mode) {
+            (false, _) => {
+                for p in 0..16 {
+                    let v = (mods >> (p * 2) & 3) as usize;
+                    let at = at(p % 4, p / 4);
+                    modulation[at] = if mode { [0, 4, 4, 8][v] } else { MOD_VALUES[v] };
+                    punch[at] = mode && v == 2;
+                }
+            }
+            (true, false) => {
+                for p in 0..32 {
+                    modulation[at(p % 8, p / 8)] = if mods >> p & 1 != 0 { 8 } else { 0 };
+                }
+            }
+            (true, true) => {
+                let mut mods = mods;
+                // 0 - horizontal and vertical, 1 - horizontal only, 2 - vertical only
+                let mut kind = 0;
+                if mods & 1 != 0 {
+                    kind = if mods & 1 << 20 != 0 { 2 } else { 1 };
+                    // the center pixel lost its low bit to the kind flag
+                    mods = mods & !(1 << 20) | (mods >> 21 & 1) << 20;
+                }
+                mods = mods & !1 | (mods >> 1 & 1);
+
+                for y in 0..BLOCK_H {
+                    for x in 0..8 {
+                        if (x ^ y) & 1 == 0 {
+                            modulation[at(x, y)] = MOD_VALUES[(mods & 3) as usize];
+                            mods >>= 2;
+                        } else {
+                            interpolate[at(x, y)] = Some(kind);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // 2bpp pixels which aren't stored are averaged from the stored neighbours
+    let stored = modulation.clone();
+    for (i, kind) in interpolate.iter().enumerate() {
+        let kind = match kind {
+            Some(k) => *k,
+            None => continue,
+        };
+
+        let (x, y) = (i % gw, i / gw);
+        let h = stored[y * gw + (x + gw - 1) % gw] + stored[y * gw + (x + 1) % gw];
+        let v = stored[(y + gh - 1) % gh * gw + x] + stored[(y + 1) % gh * gw + x];
+
+        modulation[i] = match kind {
+            0 => (h + v + 2) / 4,
+            1 => h.div_ceil(2),
+            _ => v.div_ceil(2),
+        };
+    }
+
+    let mut img = RgbaImage::new(w, h);
+    let weight_sum = (block_w * BLOCK_H) as u32;
+
+    for (x, y, px) in img.enumerate_pixels_mut() {
+        let (x, y) = (x as usize, y as usize);
+
+        // blocks whose centers surround the pixel, wrapping around the texture
+        let rx = x + gw - block_w / 2;
+        let ry = y + gh - BLOCK_H / 2;
+        let (bx0, by0) = (rx / block_w % bx_n, ry / BLOCK_H % by_n);
+        let (bx1, by1) = ((bx0 + 1) % bx_n, (by0 + 1) % by_n);
+        let (wx, wy) = ((rx % block_w) as u32, (ry % BLOCK_H) as u32);
+
+        let corners = [
+            (&colors[by0 * bx_n + bx0], (block_w as u32 - wx) * (BLOCK_H as u32 - wy)),
+            (&colors[by0 * bx_n + bx1], wx * (BLOCK_H as u32 - wy)),
+            (&colors[by1 * bx_n + bx0], (block_w as u32 - wx) * wy),
+            (&colors[by1 * bx_n + bx1], wx * wy),
+        ];
+
+        let m = modulation[y * gw + x];
+        let mut res = [0u8; 4];
+
+        for (ch, out) in res.iter_mut().enumerate() {
+            // 16x the interpolated value
+            let a16 = corners.iter().map(|(c, w)| c.a[ch] * w).sum::<u32>() * 16 / weight_sum;
+            let b16 = corners.iter().map(|(c, w)| c.b[ch] * w).sum::<u32>() * 16 / weight_sum;
+
+            let (a, b) = match ch {
+                3 => (a16 + (a16 >> 4), b16 + (b16 >> 4)), // 4 bits
+                _ => ((a16 >> 1) + (a16 >> 6), (b16 >> 1) + (b16 >> 6)), // 5 bits
+            };
+
+            *out = ((a * (8 - m) + b * m) / 8) as u8;
+        }
+
+        if punch[y * gw + x] {
+            res[3] = 0;
+        }
+
+        *px = Rgba(res);
+    }
+
+    Ok(img)
+}
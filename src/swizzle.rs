@@ -0,0 +1,77 @@
+//! Texture data reordering of console GPUs.
+
+/// Position of the `i`-th unit (pixel or block) of `w x h` Morton-ordered data.
+///
+/// Both dimensions are powers of two. Data is split into squares along the longer
+/// dimension, each square is a Z-order curve with `y` in even bits and `x` in odd bits.
+/// This is the order of PS Vita swizzled textures and of PVRTC blocks
+pub(crate) fn morton_pos(i: usize, w: usize, h: usize) -> (usize, usize) {
+    let min = w.min(h);
+    let k = min.trailing_zeros();
+    let tile = i >> (2 * k);
+    let code = i & (min * min - 1);
+    let (x, y) = (compact_bits(code >> 1), compact_bits(code));
+
+    if w > h { (tile * min + x, y) } else { (x, tile * min + y) }
+}
+
+/// Even bits of `v` packed together
+fn compact_bits(v: usize) -> usize {
+    let mut res = 0;
+    for bit in 0..usize::BITS / 2 {
+        res |= (v >> (bit * 2) & 1) << bit;
+    }
+    res
+}
+
+/// Reorder Morton-ordered `w x h` units of `unit_sz` bytes into rows.
+/// `w` and `h` are rounded up to powers of two for the source layout, output is cropped
+pub(crate) fn unswizzle_morton(data: &[u8], w: usize, h: usize, unit_sz: usize) -> Option<Vec<u8>> {
+    let (pw, ph) = (w.next_power_of_two(), h.next_power_of_two());
+    let src = data.get(..pw * ph * unit_sz)?;
+    let mut res = vec![0u8; w * h * unit_sz];
+
+    for (i, unit) in src.chunks_exact(unit_sz).enumerate() {
+        let (x, y) = morton_pos(i, pw, ph);
+        if x < w && y < h {
+            let at = (y * w + x) * unit_sz;
+            res[at..at + unit_sz].copy_from_slice(unit);
+        }
+    }
+
+    Some(res)
+}
+
+/// Reorder Tegra X1 block-linear data of `w x h` units of `unit_sz` bytes into rows.
+///
+/// Data is made of 512-byte GOBs (64 bytes x 8 rows), stacked by `block_height` GOBs into
+/// blocks, which are laid out row by row. Rows are padded to whole GOBs, the height to whole blocks.
+/// None if the data is too short or its size doesn't fit into usize
+pub(crate) fn deswizzle_block_linear(
+    data: &[u8],
+    w: usize,
+    h: usize,
+    unit_sz: usize,
+    block_height: usize,
+) -> Option<Vec<u8>> {
+    let width_gobs = w.checked_mul(unit_sz)?.div_ceil(64);
+    let block_rows = h.div_ceil(8 * block_height);
+    let src = data.get(..width_gobs.checked_mul(block_rows)?.checked_mul(512 * block_height)?)?;
+    // padded source is larger, so the size fits
+    let mut res = vec![0u8; w * h * unit_sz];
+
+    for y in 0..h {
+        for x in 0..w {
+            let xb = x * unit_sz;
+            let gob = (y / (8 * block_height)) * 512 * block_height * width_gobs
+                + (xb / 64) * 512 * block_height
+                + (y % (8 * block_height) / 8) * 512;
+            let at = gob + (xb % 64) / 32 * 256 + (y % 8) / 2 * 64 + (xb % 32) / 16 * 32 + (y % 2) * 16 + xb % 16;
+
+            let dst = (y * w + x) * unit_sz;
+            res[dst..dst + unit_sz].copy_from_slice(&src[at..at + unit_sz]);
+        }
+    }
+
+    Some(res)
+}
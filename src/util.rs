@@ -1,4 +1,4 @@
-use std::fmt::{self, Write, LowerHex, UpperHex, Formatter};
+use std::fmt::{self, Display, Write, LowerHex, UpperHex, Formatter};
 
 pub struct Hex<'a>(pub &'a [u8]);
 
@@ -13,3 +13,23 @@ macro_rules! fmt_impl { ($trait:ident, $fmt:expr) => {
 
 fmt_impl!(LowerHex, "{:02x}");
 fmt_impl!(UpperHex, "{:02X}");
+
+/// Escapes string for use inside of JSON string literal
+pub struct JsonEsc<'a>(pub &'a str);
+
+impl Display for JsonEsc<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+//! Bounds checks of chunk ranges and coordinates.
+//!
+//! [`draw_sprites`](crate::draw::draw_sprites) performs the same checks for the
+//! sprites it draws, these functions allow to check the whole lay beforehand.
+
+use super::*;
+
+/// Check every sprite chunk range and every chunk destination against the canvas
+pub fn validate_lay(lay: &ParsedLay) -> Result<(), SgSpriteErr> {
+    for (i, s) in lay.sprites.iter().enumerate() {
+        sprite_chunks(lay, s, i)?;
+    }
+
+    for (i, c) in lay.chunks.iter().enumerate() {
+        dst_pos(lay, c, i)?;
+    }
+
+    Ok(())
+}
+
+/// Check source rectangles of chunks used by sprites against the source image size
+pub fn validate_source(lay: &ParsedLay, src_dim: (u32, u32)) -> Result<(), SgSpriteErr> {
+    for (i, s) in lay.sprites.iter().enumerate() {
+        sprite_chunks(lay, s, i)?;
+
+        for c in s.chunk_offset..s.chunk_offset + s.chunk_count {
+            src_pos(lay, &lay.chunks[c], c, src_dim)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Chunks of the sprite, `index` is the sprite index used for error reporting
+pub fn sprite_chunks<'a>(lay: &'a ParsedLay, s: &Sprite, index: usize) -> Result<&'a [Chunk], SgSpriteErr> {
+    s.chunk_offset
+        .checked_add(s.chunk_count)
+        .and_then(|end| lay.chunks.get(s.chunk_offset..end))
+        .ok_or(SgSpriteErr::ChunkRange {
+            sprite: index,
+            offset: s.chunk_offset,
+            count: s.chunk_count,
+            total: lay.chunks.len(),
+        })
+}
+
+/// Upper-left corner of the chunk on the canvas
+pub fn dst_pos(lay: &ParsedLay, c: &Chunk, index: usize) -> Result<(u32, u32), SgSpriteErr> {
+    let (x_min, y_min) = lay.sprite_xy_min;
+    let x = c.img_x as i64 - x_min.min(0) as i64;
+    let y = c.img_y as i64 - y_min.min(0) as i64;
+    let (w, h) = (lay.sprite_w, lay.sprite_h);
+
+    rect_in(x, y, lay.block_size, (w, h)).ok_or(SgSpriteErr::DstOutOfBounds { chunk: index, x, y, w, h })
+}
+
+/// Upper-left corner of the chunk in the source image
+pub fn src_pos(lay: &ParsedLay, c: &Chunk, index: usize, (w, h): (u32, u32)) -> Result<(u32, u32), SgSpriteErr> {
+    let x = c.chunk_x as i64 - 1;
+    let y = c.chunk_y as i64 - 1;
+
+    rect_in(x, y, lay.block_size, (w, h)).ok_or(SgSpriteErr::SrcOutOfBounds { chunk: index, x, y, w, h })
+}
+
+fn rect_in(x: i64, y: i64, block: u32, (w, h): (u32, u32)) -> Option<(u32, u32)> {
+    let fits = x >= 0
+        && y >= 0
+        && x + block as i64 <= w as i64
+        && y + block as i64 <= h as i64;
+
+    if fits { Some((x as u32, y as u32)) } else { None }
+}
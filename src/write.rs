@@ -0,0 +1,80 @@
+//! `.lay` file writer, mirrors [`parse_lay`](crate::parse::parse_lay).
+//!
+//! Sprite info bytes (unless `id` or `sprite_type` were changed) and trailing data are written
+//! as they were parsed, in the parsed byte order, so uncompressed output is byte-exact with
+//! the parsed file. Compressed output is byte-exact after decompression, but compressed stream
+//! itself may differ from the original, since the encoder isn't the same.
+
+use super::*;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
+use libflate::{deflate, gzip, zlib};
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+/// Write `lay` in the given compression.
+/// Compressed stream is written with the default libflate settings, not the ones of the parsed file
+pub fn write_lay(lay: &ParsedLay, out: impl Write, compression: Compression) -> Result<(), SgSpriteErr> {
+    match compression {
+        Compression::Raw => write_lay_impl(lay, io::BufWriter::new(out)),
+        Compression::Zlib => {
+            let mut z = zlib::Encoder::new(out)?;
+            write_lay_impl(lay, &mut z)?;
+            z.finish().into_result()?;
+            Ok(())
+        }
+        Compression::Deflate => {
+            let mut z = deflate::Encoder::new(out);
+            write_lay_impl(lay, &mut z)?;
+            z.finish().into_result()?;
+            Ok(())
+        }
+        Compression::Gzip => {
+            let mut z = gzip::Encoder::new(out)?;
+            write_lay_impl(lay, &mut z)?;
+            z.finish().into_result()?;
+            Ok(())
+        }
+    }
+}
+
+/// Same as [`write_lay`] into a new buffer
+pub fn write_lay_bytes(lay: &ParsedLay, compression: Compression) -> Result<Vec<u8>, SgSpriteErr> {
+    let mut buf = Vec::new();
+    write_lay(lay, &mut buf, compression)?;
+    Ok(buf)
+}
+
+fn write_lay_impl(lay: &ParsedLay, bf: impl Write) -> Result<(), SgSpriteErr> {
+    match lay.endian {
+        Endian::Little => write_lay_body::<LittleEndian>(lay, bf),
+        Endian::Big => write_lay_body::<BigEndian>(lay, bf),
+    }
+}
+
+fn write_lay_body<B: ByteOrder>(lay: &ParsedLay, mut bf: impl Write) -> Result<(), SgSpriteErr> {
+    // write header
+    bf.write_u32::<B>(u32_field("sprite count", lay.sprites.len())?)?;
+    bf.write_u32::<B>(u32_field("chunk count", lay.chunks.len())?)?;
+
+    // write sprites, info is a little-endian u32
+    for s in &lay.sprites {
+        bf.write_u32::<B>(u32::from_le_bytes(s.info_bytes()))?;
+        bf.write_u32::<B>(u32_field("chunk offset", s.chunk_offset)?)?;
+        bf.write_u32::<B>(u32_field("chunk count", s.chunk_count)?)?;
+    }
+
+    // write chunks
+    for c in &lay.chunks {
+        for &v in &[c.img_x, c.img_y, c.chunk_x, c.chunk_y] {
+            bf.write_f32::<B>(v as f32)?;
+        }
+    }
+
+    bf.write_all(&lay.trailing)?;
+    bf.flush()?;
+    Ok(())
+}
+
+fn u32_field(field: &'static str, value: usize) -> Result<u32, SgSpriteErr> {
+    u32::try_from(value).map_err(|_| SgSpriteErr::FieldOverflow { field, value })
+}
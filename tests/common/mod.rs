@@ -0,0 +1,44 @@
+//! Test data shared between test binaries.
+
+#![allow(dead_code)]
+
+pub fn u32_le(v: u32, lay: &mut Vec<u8>) {
+    lay.extend_from_slice(&v.to_le_bytes());
+}
+
+/// 2 sprites (base, sub) and 3 chunks, composed into 64x64 from 128x64 source
+pub fn raw_lay() -> Vec<u8> {
+    let mut lay = Vec::new();
+
+    // header
+    u32_le(2, &mut lay);
+    u32_le(3, &mut lay);
+
+    // sprites: base, sub
+    lay.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]);
+    u32_le(0, &mut lay);
+    u32_le(2, &mut lay);
+    lay.extend_from_slice(&[0x02, 0x00, 0x00, 0x20]);
+    u32_le(2, &mut lay);
+    u32_le(1, &mut lay);
+
+    // chunks
+    for c in &[[-32f32, -32., 1., 1.], [0., -32., 33., 1.], [-32., 0., 65., 1.]] {
+        for f in c {
+            lay.extend_from_slice(&f.to_le_bytes());
+        }
+    }
+
+    lay
+}
+
+/// 128x64 source png for [`raw_lay`], each 32x32 block has its own color
+pub fn source_png() -> Vec<u8> {
+    let img = image::RgbaImage::from_fn(128, 64, |x, y| {
+        image::Rgba([(x / 32 * 64) as u8, (y / 32 * 64) as u8, 0, 255])
+    });
+
+    let mut buf = Vec::new();
+    image::png::PngEncoder::new(&mut buf).encode(&img, 128, 64, image::ColorType::Rgba8).unwrap();
+    buf
+}
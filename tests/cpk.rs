@@ -0,0 +1,276 @@
+mod common;
+
+use common::*;
+use sg_sprite::*;
+use std::io::Cursor;
+
+const LIMIT: u64 = 1 << 20;
+
+enum Cell<'a> {
+    U32(u32),
+    U64(u64),
+    Str(&'a str),
+}
+
+/// `@UTF` table with per-row columns only
+fn utf_table(columns: &[&str], rows: &[Vec<Cell>]) -> Vec<u8> {
+    let mut strings = b"<NULL>\0".to_vec();
+    let mut string = |s: &str| {
+        let at = strings.len() as u32;
+        strings.extend_from_slice(s.as_bytes());
+        strings.push(0);
+        at
+    };
+
+    let mut cols = Vec::new();
+    for (i, name) in columns.iter().enumerate() {
+        let ty = match rows[0][i] {
+            Cell::U32(_) => 0x04,
+            Cell::U64(_) => 0x06,
+            Cell::Str(_) => 0x0A,
+        };
+        cols.push(0x50 | ty);
+        cols.extend_from_slice(&string(name).to_be_bytes());
+    }
+
+    let mut row_data = Vec::new();
+    for r in rows {
+        for c in r {
+            match c {
+                Cell::U32(v) => row_data.extend_from_slice(&v.to_be_bytes()),
+                Cell::U64(v) => row_data.extend_from_slice(&v.to_be_bytes()),
+                Cell::Str(s) => row_data.extend_from_slice(&string(s).to_be_bytes()),
+            }
+        }
+    }
+
+    let rows_at = 0x18 + cols.len() as u32;
+    let strings_at = rows_at + row_data.len() as u32;
+    let data_at = strings_at + strings.len() as u32;
+    let row_sz = row_data.len() / rows.len();
+
+    let mut t = b"@UTF".to_vec();
+    for v in &[data_at, rows_at, strings_at, data_at, 0] {
+        t.extend_from_slice(&v.to_be_bytes());
+    }
+    t.extend_from_slice(&(columns.len() as u16).to_be_bytes());
+    t.extend_from_slice(&(row_sz as u16).to_be_bytes());
+    t.extend_from_slice(&(rows.len() as u32).to_be_bytes());
+    t.extend(cols);
+    t.extend(row_data);
+    t.extend(strings);
+    t
+}
+
+fn chunk(magic: &[u8; 4], packet: &[u8]) -> Vec<u8> {
+    let mut c = magic.to_vec();
+    c.extend_from_slice(&0xFFu32.to_le_bytes());
+    c.extend_from_slice(&(packet.len() as u64).to_le_bytes());
+    c.extend_from_slice(packet);
+    c
+}
+
+fn mask(data: &mut [u8]) {
+    let mut m: u32 = 0x655F;
+    for b in data {
+        *b ^= m as u8;
+        m = m.wrapping_mul(0x4115);
+    }
+}
+
+/// Greedy CRILAYLA encoder, the first 0x100 bytes are stored as is
+fn crilayla(data: &[u8]) -> Vec<u8> {
+    let mut bits: Vec<bool> = Vec::new();
+    let put = |v: usize, n: usize, bits: &mut Vec<bool>| (0..n).rev().for_each(|i| bits.push(v >> i & 1 == 1));
+
+    let body = &data[0x100..];
+    let mut pos = body.len(); // body[pos..] is encoded
+    while pos > 0 {
+        let (mut best_len, mut best_dist) = (0, 0);
+        for dist in 3..=(0x1FFF + 3).min(body.len() - pos) {
+            // source may overlap the bytes being encoded, like in any LZ77
+            let len = (0..pos).take_while(|k| body[pos - 1 - k] == body[pos + dist - 1 - k]).count();
+            if len > best_len {
+                best_len = len;
+                best_dist = dist;
+            }
+        }
+
+        if best_len < 3 {
+            put(0, 1, &mut bits);
+            put(body[pos - 1] as usize, 8, &mut bits);
+            pos -= 1;
+            continue;
+        }
+
+        put(1, 1, &mut bits);
+        put(best_dist - 3, 13, &mut bits);
+        let mut rem = best_len - 3;
+        let mut maxed = true;
+        for &n in &[2, 3, 5, 8] {
+            let max = (1 << n) - 1;
+            put(rem.min(max), n, &mut bits);
+            if rem < max {
+                maxed = false;
+                break;
+            }
+            rem -= max;
+        }
+        while maxed {
+            put(rem.min(0xFF), 8, &mut bits);
+            maxed = rem >= 0xFF;
+            rem -= rem.min(0xFF);
+        }
+        pos -= best_len;
+    }
+
+    // bytes are read from the end, bits from MSB
+    let mut stream: Vec<u8> = bits
+        .chunks(8)
+        .map(|c| c.iter().enumerate().fold(0, |b, (i, bit)| b | (*bit as u8) << (7 - i)))
+        .collect();
+    stream.reverse();
+
+    let mut res = b"CRILAYLA".to_vec();
+    res.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    res.extend_from_slice(&(stream.len() as u32).to_le_bytes());
+    res.extend(stream);
+    res.extend_from_slice(&data[..0x100]);
+    res
+}
+
+fn cpk(files: &[(&str, &str, Vec<u8>, usize)], masked: bool) -> Vec<u8> {
+    const TOC_AT: usize = 0x800;
+
+    // file offsets are relative to TOC since it comes before the content,
+    // TOC size doesn't depend on them, so it's built twice
+    let toc = |content_at: usize| {
+        let mut offset = content_at - TOC_AT;
+        let rows: Vec<_> = files
+            .iter()
+            .map(|(dir, name, data, extract_size)| {
+                offset += data.len();
+                vec![
+                    Cell::Str(dir),
+                    Cell::Str(name),
+                    Cell::U32(data.len() as u32),
+                    Cell::U32(*extract_size as u32),
+                    Cell::U64((offset - data.len()) as u64),
+                ]
+            })
+            .collect();
+
+        let mut toc = utf_table(&["DirName", "FileName", "FileSize", "ExtractSize", "FileOffset"], &rows);
+        if masked {
+            mask(&mut toc);
+        }
+        chunk(b"TOC ", &toc)
+    };
+
+    let content_at = TOC_AT + toc(TOC_AT).len();
+    let header = utf_table(
+        &["ContentOffset", "TocOffset"],
+        &[vec![Cell::U64(content_at as u64), Cell::U64(TOC_AT as u64)]],
+    );
+
+    let mut res = chunk(b"CPK ", &header);
+    res.resize(TOC_AT, 0);
+    res.extend(toc(content_at));
+    files.iter().for_each(|f| res.extend_from_slice(&f.2));
+    res
+}
+
+#[test]
+fn crilayla_roundtrip() {
+    let mut data: Vec<u8> = (0..0x100).map(|i| i as u8).collect();
+    data.extend(raw_lay());
+    data.extend_from_slice(&[0xAB; 600]); // long VLE run
+    data.extend(raw_lay());
+
+    let packed = crilayla(&data);
+    assert!(packed.len() < data.len());
+    assert_eq!(decompress_crilayla(&packed, LIMIT).unwrap(), data);
+
+    let mut broken = packed.clone();
+    broken[8] ^= 0x40; // output size
+    assert!(decompress_crilayla(&broken, LIMIT).is_err());
+    assert!(decompress_crilayla(&packed[..0x20], LIMIT).is_err());
+
+    // output size is checked before decoding
+    let mut huge = packed;
+    huge[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+    match decompress_crilayla(&huge, LIMIT) {
+        Err(SgSpriteErr::BadArchive(msg)) => assert!(msg.contains("limit"), "{}", msg),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn crilayla_known_answer() {
+    // read from the last byte, MSB first:
+    // 0 'z' | 0 'y' | 0 'x' | 1, distance 3 - 3: 13 bits 0, length 3 + 3 + 7: 11 111 00000 | padding
+    let mut packed = b"CRILAYLA".to_vec();
+    packed.extend_from_slice(&16u32.to_le_bytes());
+    packed.extend_from_slice(&7u32.to_le_bytes());
+    packed.extend_from_slice(&[0x00, 0x7C, 0x00, 0x10, 0x4F, 0x1E, 0x3D]);
+    packed.extend_from_slice(&[0x55; 0x100]);
+
+    let mut expected = vec![0x55; 0x100];
+    expected.extend_from_slice(b"zxyzxyzxyzxyzxyz");
+    assert_eq!(decompress_crilayla(&packed, LIMIT).unwrap(), expected);
+    assert!(decompress_crilayla(&packed, 0x10F).is_err());
+}
+
+#[test]
+fn cpk_pairs_and_read() {
+    let png = source_png();
+    let mut big_lay = raw_lay();
+    big_lay.resize(0x180, 0); // trailing data, enough for compression
+
+    for &masked in &[false, true] {
+        let data = cpk(
+            &[
+                ("chara", "CRS_A.lay", crilayla(&big_lay), big_lay.len()),
+                ("chara", "CRS_A.png", png.clone(), png.len()),
+                ("", "CRS_B.lay", raw_lay(), raw_lay().len()),
+            ],
+            masked,
+        );
+
+        let mut cpk = CpkArchive::open(Cursor::new(data)).unwrap();
+        let names: Vec<_> = cpk.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["chara/CRS_A.lay", "chara/CRS_A.png", "CRS_B.lay"]);
+        assert_eq!(cpk.lay_pairs(), vec![(0, Some(1)), (2, None)]);
+
+        let lay = parse_lay_bytes(&cpk.read(0).unwrap()).unwrap();
+        assert_eq!(lay.trailing.len(), 0x180 - raw_lay().len());
+        assert_eq!(cpk.read(1).unwrap(), png);
+        assert_eq!(cpk.read(2).unwrap(), raw_lay());
+    }
+}
+
+#[test]
+fn cpk_malformed() {
+    let data = cpk(&[("", "CRS_B.lay", raw_lay(), raw_lay().len())], false);
+
+    let mut no_toc = data.clone();
+    no_toc[0x800] = b'X';
+    match CpkArchive::open(Cursor::new(no_toc)) {
+        Err(SgSpriteErr::BadArchive(msg)) => assert!(msg.contains("TOC"), "{}", msg),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+
+    match CpkArchive::open(Cursor::new(&data[..data.len() - 1])) {
+        Err(SgSpriteErr::BadArchive(msg)) => assert!(msg.contains("CRS_B.lay"), "{}", msg),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+
+    // header table of u32::MAX rows, 0 bytes each
+    let mut empty_rows = data.clone();
+    empty_rows[0x2A..0x2C].copy_from_slice(&0u16.to_be_bytes());
+    empty_rows[0x2C..0x30].copy_from_slice(&u32::MAX.to_be_bytes());
+    match CpkArchive::open(Cursor::new(empty_rows)) {
+        Err(SgSpriteErr::BadArchive(msg)) => assert!(msg.contains("zero size"), "{}", msg),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
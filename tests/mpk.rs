@@ -0,0 +1,116 @@
+mod common;
+
+use common::*;
+use sg_sprite::*;
+use std::io::{Cursor, Write};
+
+/// Build v2 archive, compressed entries are zlib streams
+fn mpk_v2(entries: &[(&str, &[u8], bool)]) -> Vec<u8> {
+    let mut mpk = b"MPK\0".to_vec();
+    mpk.extend_from_slice(&0u16.to_le_bytes());
+    mpk.extend_from_slice(&2u16.to_le_bytes());
+    mpk.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+    mpk.resize(0x40 + entries.len() * 0x100, 0);
+
+    for (i, (name, data, compressed)) in entries.iter().enumerate() {
+        let stored = if *compressed {
+            let mut z = libflate::zlib::Encoder::new(Vec::new()).unwrap();
+            z.write_all(data).unwrap();
+            z.finish().into_result().unwrap()
+        } else {
+            data.to_vec()
+        };
+
+        let mut entry = Vec::new();
+        entry.extend_from_slice(&(*compressed as u32).to_le_bytes());
+        entry.extend_from_slice(&(i as u32).to_le_bytes());
+        entry.extend_from_slice(&(mpk.len() as u64).to_le_bytes());
+        entry.extend_from_slice(&(stored.len() as u64).to_le_bytes());
+        entry.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        entry.extend_from_slice(name.as_bytes());
+
+        let at = 0x40 + i * 0x100;
+        mpk[at..at + entry.len()].copy_from_slice(&entry);
+        mpk.extend(stored);
+    }
+
+    mpk
+}
+
+#[test]
+fn mpk_pairs_and_render() {
+    let png = source_png();
+    let data = mpk_v2(&[
+        ("CRS_A.lay", &raw_lay(), true),
+        ("CRS_A.png", &png, false),
+        ("CRS_B_.lay", &raw_lay(), false),
+        ("README.txt", b"not a sprite", false),
+    ]);
+
+    let mut mpk = MpkArchive::open(Cursor::new(data)).unwrap();
+    let names: Vec<_> = mpk.entries().iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, vec!["CRS_A.lay", "CRS_A.png", "CRS_B_.lay", "README.txt"]);
+    assert_eq!(mpk.lay_pairs(), vec![(0, Some(1)), (2, None)]);
+    assert_eq!(mpk.find("CRS_A.png"), Some(1));
+
+    let lay = parse_lay_bytes(&mpk.read(0).unwrap()).unwrap();
+    assert_eq!(mpk.read(2).unwrap(), raw_lay());
+    assert_eq!(mpk.read(1).unwrap(), png);
+
+    let mut src = draw_prep_bytes(&mpk.read(1).unwrap()).unwrap();
+    let graph = DepGraph::resolve_dep_graph(&lay);
+    let leaf = graph.get_leaf_sprites().next().unwrap();
+    let sprite = draw_sprites(&mut src, &graph.resolve_layers(leaf).unwrap(), 1, &lay).unwrap();
+
+    assert_eq!(sprite.dimensions(), (64, 64));
+    assert_eq!(sprite.get_pixel(40, 8).0, [64, 0, 0, 255]); // chunk 1, second source block
+    assert_eq!(sprite.get_pixel(8, 40).0, [128, 0, 0, 255]); // chunk 2 of the sub sprite
+}
+
+#[test]
+fn mpk_malformed() {
+    match MpkArchive::open(Cursor::new(b"MPL\0".to_vec())) {
+        Err(SgSpriteErr::BadArchive(_)) => (),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+
+    let mut data = mpk_v2(&[("CRS_A.lay", &raw_lay(), false)]);
+    data.truncate(data.len() - 1);
+    match MpkArchive::open(Cursor::new(data)) {
+        Err(SgSpriteErr::BadArchive(msg)) => assert!(msg.contains("CRS_A.lay"), "{}", msg),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn mpk_huge_entry_count() {
+    for &count in &[u64::MAX / 0x100, u64::MAX / 0x100 + 1, u64::MAX] {
+        let mut data = b"MPK\0".to_vec();
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&count.to_le_bytes());
+        data.resize(0x40, 0);
+
+        match MpkArchive::open(Cursor::new(data)) {
+            Err(SgSpriteErr::BadArchive(msg)) => assert!(msg.contains("entry table"), "{}", msg),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}
+
+#[test]
+fn mpk_entry_limit() {
+    let zeros = vec![0u8; 1 << 20];
+    let data = mpk_v2(&[("zeros.bin", &zeros, true)]);
+
+    let limits = Limits { decompressed: 1 << 16, ..Limits::default() };
+    let mut mpk = MpkArchive::open_with(Cursor::new(data.clone()), &limits).unwrap();
+    match mpk.read(0) {
+        Err(SgSpriteErr::TooLarge { limit }) => assert_eq!(limit, 1 << 16),
+        r => panic!("unexpected result {:?}", r.map(|d| d.len())),
+    }
+
+    let limits = Limits { decompressed: 1 << 20, ..Limits::default() };
+    let mut mpk = MpkArchive::open_with(Cursor::new(data), &limits).unwrap();
+    assert_eq!(mpk.read(0).unwrap(), zeros);
+}
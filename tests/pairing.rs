@@ -0,0 +1,165 @@
+mod common;
+
+use common::*;
+use sg_sprite::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Archive with empty entries, only names matter for pairing
+struct Names(Vec<&'static str>);
+
+impl Archive for Names {
+    fn entry_count(&self) -> usize {
+        self.0.len()
+    }
+
+    fn entry_name(&self, index: usize) -> &str {
+        self.0[index]
+    }
+
+    fn read(&mut self, _index: usize) -> Result<Vec<u8>, SgSpriteErr> {
+        Ok(Vec::new())
+    }
+}
+
+/// Fresh directory in `./target` with an empty file for each name
+fn files_dir(dir: &str, names: &[&str]) -> PathBuf {
+    let dir = Path::new("./target").join(dir);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+
+    for n in names {
+        fs::write(dir.join(n), b"").unwrap();
+    }
+    dir
+}
+
+fn found(pairing: &Pairing, dir: &Path, lay: &str) -> Option<String> {
+    let src = pairing.find_source(&dir.join(lay)).ok()?;
+    Some(src.file_name().unwrap().to_str().unwrap().to_string())
+}
+
+#[test]
+fn pair_rules() {
+    let p = Pairing::default();
+    assert_eq!(p.rank("CRS_A.png", "CRS_A"), Some((0, 0)));
+    assert_eq!(p.rank("CRS_A.gxt", "CRS_A"), Some((1, 0)));
+    assert_eq!(p.rank("CRS_A.png", "CRS_A_"), Some((0, 1)));
+    assert_eq!(p.rank("CRS_A (Image 0).png", "CRS_A"), Some((0, 2)));
+    assert_eq!(p.rank("CRS_A(Image 0).png", "CRS_A"), Some((0, 2)));
+    assert_eq!(p.rank("CRS_AB.png", "CRS_A"), None);
+    assert_eq!(p.rank("CRS_A.lay", "CRS_A"), None);
+
+    let re = PairRule::regex("^src_(.+)$").unwrap();
+    assert!(re.matches("src_CRS_A", "CRS_A"));
+    assert!(!re.matches("src_CRS_AB", "CRS_A"));
+    assert!(PairRule::regex("^src_.+$").is_err());
+    assert!(PairRule::regex("(").is_err());
+}
+
+#[test]
+fn pair_rules_from_opts() {
+    let regex = Some("^src_(.+)$".to_string());
+
+    // regex is prepended to the default rules
+    let p = Opts { pair_regex: regex.clone(), ..Opts::default() }.pairing().unwrap();
+    assert_eq!(p.rank("src_CRS_A.png", "CRS_A"), Some((0, 0)));
+    assert_eq!(p.rank("CRS_A.png", "CRS_A"), Some((0, 1)));
+    assert_eq!(p.rank("CRS_A (Image 0).png", "CRS_A"), Some((0, 3)));
+
+    // and to the game rules
+    let game = GameProfile::by_id("sg0").ok();
+    let p = Opts { pair_regex: regex.clone(), game, ..Opts::default() }.pairing().unwrap();
+    assert_eq!(p.rank("src_CRS_A.png", "CRS_A"), Some((0, 0)));
+    assert_eq!(p.rank("CRS_A.png", "CRS_A_"), Some((0, 2)));
+    assert_eq!(p.rank("CRS_A (Image 0).png", "CRS_A"), None);
+
+    // explicit rules take precedence over the game ones
+    let p = Opts { pair_regex: regex, game, pair_rules: vec![PairRule::ImageSuffix], ..Opts::default() };
+    let p = p.pairing().unwrap();
+    assert_eq!(p.rank("CRS_A (Image 0).png", "CRS_A"), Some((0, 1)));
+    assert_eq!(p.rank("CRS_A.png", "CRS_A"), None);
+}
+
+#[test]
+fn pair_in_directory() {
+    let dir = files_dir(
+        "test_pairing",
+        &["CRS_A.lay", "CRS_AB.png", "CRS_B_.lay", "CRS_B.png", "CRS_C.lay", "CRS_C.gxt", "CRS_C (Image 0).png"],
+    );
+
+    let p = Pairing::default();
+    // prefix of another sprite name isn't a match
+    assert_eq!(found(&p, &dir, "CRS_A.lay"), None);
+    assert_eq!(found(&p, &dir, "CRS_B_.lay"), Some("CRS_B.png".to_string()));
+    // png is preferred over textures
+    assert_eq!(found(&p, &dir, "CRS_C.lay"), Some("CRS_C (Image 0).png".to_string()));
+
+    let exact = Pairing::new(vec![PairRule::Exact]);
+    assert_eq!(found(&exact, &dir, "CRS_B_.lay"), None);
+    assert_eq!(found(&exact, &dir, "CRS_C.lay"), Some("CRS_C.gxt".to_string()));
+
+    let underscore = Pairing::new(vec![PairRule::Underscore]);
+    assert_eq!(found(&underscore, &dir, "CRS_B_.lay"), Some("CRS_B.png".to_string()));
+    assert_eq!(found(&underscore, &dir, "CRS_C.lay"), None);
+
+    let re = Pairing::new(vec![PairRule::regex("^(.+)B$").unwrap()]);
+    assert_eq!(found(&re, &dir, "CRS_A.lay"), Some("CRS_AB.png".to_string()));
+}
+
+#[test]
+fn pair_underscore_lay_file() {
+    let dir = files_dir("test_pairing_underscore", &[]);
+    fs::write(dir.join("CRS_B_.lay"), raw_lay()).unwrap();
+    fs::write(dir.join("CRS_B.png"), source_png()).unwrap();
+
+    let run = |pair_rules: Vec<PairRule>| {
+        let lay_files = vec![dir.join("CRS_B_.lay")];
+        lib_main(&Opts { lay_files, dir: Some(dir.clone()), pair_rules, ..Opts::default() }).unwrap()
+    };
+
+    let summary = run(vec![PairRule::Underscore]);
+    assert_eq!(summary.exit_code(), 0);
+    assert!(summary.rendered > 0);
+    // exact needs CRS_B_.png
+    assert_eq!(run(vec![PairRule::Exact]).exit_code(), 1);
+}
+
+#[test]
+fn pair_by_manifest() {
+    let dir = files_dir("test_manifest", &["a.lay", "b.lay", "CRS_A.png", "other.png", "b.png"]);
+    fs::write(dir.join("pairs.csv"), "lay,png\n# comment\n\na.lay, other.png\n").unwrap();
+
+    let mut p = Pairing::default();
+    p.load_manifest(&dir.join("pairs.csv")).unwrap();
+    assert_eq!(found(&p, &dir, "a.lay"), Some("other.png".to_string()));
+    // unlisted files are paired by the rules
+    assert_eq!(found(&p, &dir, "b.lay"), Some("b.png".to_string()));
+
+    fs::write(dir.join("bad.csv"), "a.lay\n").unwrap();
+    assert!(matches!(p.load_manifest(&dir.join("bad.csv")), Err(SgSpriteErr::BadPairing(_))));
+    fs::write(dir.join("missing.csv"), "c.lay,CRS_A.png\n").unwrap();
+    assert!(matches!(p.load_manifest(&dir.join("missing.csv")), Err(SgSpriteErr::BadPairing(_))));
+
+    #[cfg(feature = "json")]
+    {
+        fs::write(dir.join("pairs.json"), r#"{"b.lay": "CRS_A.png"}"#).unwrap();
+        p.load_manifest(&dir.join("pairs.json")).unwrap();
+        assert_eq!(found(&p, &dir, "b.lay"), Some("CRS_A.png".to_string()));
+    }
+}
+
+#[test]
+fn pair_in_archive_directory() {
+    let names = Names(vec!["chara/CRS_A.lay", "CRS_A.png", "chara/CRS_AB.png", "chara/CRS_B_.lay", "chara/CRS_B.png"]);
+    // sources are looked up in the lay directory only
+    assert_eq!(names.lay_pairs(), vec![(0, None), (3, Some(4))]);
+
+    let re = Pairing::new(vec![PairRule::regex("^(.+)B$").unwrap()]);
+    assert_eq!(names.lay_pairs_with(&re), vec![(0, Some(2)), (3, None)]);
+
+    let underscore = Pairing::new(vec![PairRule::Underscore]);
+    assert_eq!(names.lay_pairs_with(&underscore), vec![(0, None), (3, Some(4))]);
+}
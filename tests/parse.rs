@@ -0,0 +1,454 @@
+mod common;
+
+use common::*;
+use sg_sprite::*;
+use std::io::{Cursor, Seek, SeekFrom};
+
+fn assert_sample(lay: &ParsedLay) {
+    assert_eq!(lay.sprites.len(), 2);
+    assert_eq!(lay.chunks.len(), 3);
+    assert_eq!(lay.sprites[0].sprite_type, SpriteT::Base);
+    assert_eq!(lay.sprites[1].sprite_type, SpriteT::Sub);
+    assert_eq!(lay.base_dep, Some(0));
+    assert_eq!(lay.sub_map.get(&2), Some(&1));
+    assert_eq!((lay.sprite_w, lay.sprite_h), (64, 64));
+    assert_eq!(lay.sprite_xy_min, (-32, -32));
+    assert_eq!(lay.block_size, 32);
+}
+
+#[test]
+fn parse_from_bytes() {
+    assert_sample(&parse_lay_bytes(&raw_lay()).unwrap());
+}
+
+#[test]
+fn parse_from_reader() {
+    assert_sample(&parse_lay_read(&raw_lay()[..]).unwrap());
+}
+
+#[test]
+fn parse_from_seek_at_offset() {
+    let mut data = vec![0xAA; 5];
+    data.extend(raw_lay());
+
+    let mut cur = Cursor::new(data);
+    cur.seek(SeekFrom::Start(5)).unwrap();
+    assert_sample(&parse_lay(&mut cur).unwrap());
+}
+
+#[test]
+fn block_size_detection() {
+    let scaled = |num: i32, den: i32| {
+        let mut lay = parse_lay_bytes(&raw_lay()).unwrap();
+        for c in &mut lay.chunks {
+            c.img_x = c.img_x * num / den;
+            c.img_y = c.img_y * num / den;
+        }
+        write_lay_bytes(&lay, Compression::Raw).unwrap()
+    };
+
+    let data = scaled(1, 2);
+    let lay = parse_lay_bytes(&data).unwrap();
+    assert_eq!(lay.block_size, 16);
+    assert_eq!((lay.sprite_w, lay.sprite_h), (32, 32));
+    assert!(lay.warnings.is_empty());
+
+    let forced = ParseOpts { block_size: Some(8), ..ParseOpts::default() };
+    let lay = forced.parse_lay_bytes(&data).unwrap();
+    assert_eq!(lay.block_size, 8);
+    assert_eq!((lay.sprite_w, lay.sprite_h), (24, 24));
+
+    // 24 isn't a power of two
+    let lay = parse_lay_bytes(&scaled(3, 4)).unwrap();
+    assert_eq!(lay.block_size, DEFAULT_BLOCK_SIZE);
+    assert_eq!(lay.warnings, vec![LayWarning::UnlikelyBlockSize { detected: 24 }]);
+
+    // 64 doesn't fit the source spacing of 32
+    let lay = parse_lay_bytes(&scaled(2, 1)).unwrap();
+    assert_eq!(lay.block_size, DEFAULT_BLOCK_SIZE);
+    assert_eq!(lay.warnings, vec![LayWarning::UnlikelyBlockSize { detected: 64 }]);
+
+    // single chunk per sprite, nothing to measure
+    let mut lay = parse_lay_bytes(&data).unwrap();
+    lay.sprites[0].chunk_count = 1;
+    let data = write_lay_bytes(&lay, Compression::Raw).unwrap();
+    assert_eq!(parse_lay_bytes(&data).unwrap().block_size, DEFAULT_BLOCK_SIZE);
+}
+
+#[test]
+fn parse_truncated() {
+    let lay = raw_lay();
+
+    match parse_lay_bytes(&lay[..lay.len() - 1]) {
+        Err(SgSpriteErr::Truncated { section: LaySection::Chunks, offset }) => {
+            assert_eq!(offset, 8 + 2 * 12 + 2 * 16)
+        }
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+
+    match parse_lay_bytes(&lay[..2]) {
+        Err(SgSpriteErr::Truncated { section: LaySection::Header, offset: 0 }) => (),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn parse_unknown_type() {
+    let mut lay = raw_lay();
+    lay[8 + 12 + 3] = 0x70;
+
+    match parse_lay_bytes(&lay) {
+        Err(e @ SgSpriteErr::UnknownSpriteType { type_id: 0x70, .. }) => {
+            assert_eq!(e.offset(), Some(8 + 12))
+        }
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+
+    let skip = ParseOpts { unknown_types: UnknownTypePolicy::Skip, ..ParseOpts::default() };
+    let parsed = skip.parse_lay_bytes(&lay).unwrap();
+    assert_eq!(parsed.sprites.len(), 1);
+    assert_eq!(
+        parsed.warnings,
+        vec![LayWarning::UnknownSpriteType { sprite: 1, type_id: 0x70, skipped: true }]
+    );
+
+    let standalone = ParseOpts { unknown_types: UnknownTypePolicy::Standalone, ..ParseOpts::default() };
+    let parsed = standalone.parse_lay_bytes(&lay).unwrap();
+    assert_eq!(parsed.sprites[1].sprite_type, SpriteT::Unknown(0x70));
+    assert_eq!(write_lay_bytes(&parsed, Compression::Raw).unwrap(), lay);
+
+    let graph = DepGraph::resolve_dep_graph(&parsed);
+    let leaves: Vec<_> = graph.get_leaf_sprites().map(|n| n.sprite.id).collect();
+    assert_eq!(leaves, vec![1, 2]);
+}
+
+#[test]
+fn write_raw_roundtrip() {
+    let orig = raw_lay();
+    let lay = parse_lay_bytes(&orig).unwrap();
+    assert_eq!(lay.compression, Compression::Raw);
+    assert_eq!(write_lay_bytes(&lay, Compression::Raw).unwrap(), orig);
+}
+
+#[test]
+fn write_compressed_roundtrip() {
+    let orig = raw_lay();
+
+    for &comp in &[Compression::Zlib, Compression::Deflate, Compression::Gzip] {
+        let packed = write_lay_bytes(&parse_lay_bytes(&orig).unwrap(), comp).unwrap();
+        assert_eq!(detect_compression(&packed[..8]), comp);
+
+        let lay = parse_lay_bytes(&packed).unwrap();
+        assert_sample(&lay);
+        assert_eq!(lay.compression, comp);
+        assert_eq!(write_lay_bytes(&lay, Compression::Raw).unwrap(), orig);
+    }
+}
+
+#[test]
+fn write_roundtrip_all_formats() {
+    for &endian in &[Endian::Little, Endian::Big] {
+        let mut lay = parse_lay_bytes(&raw_lay()).unwrap();
+        lay.endian = endian;
+        let raw = write_lay_bytes(&lay, Compression::Raw).unwrap();
+
+        for &comp in &[Compression::Raw, Compression::Zlib, Compression::Deflate, Compression::Gzip] {
+            let packed = write_lay_bytes(&lay, comp).unwrap();
+            // compressed stream isn't byte-exact, its contents are
+            assert_eq!(decompress_lay(&packed).unwrap().0, raw, "{:?} {:?}", endian, comp);
+
+            let parsed = parse_lay_bytes(&packed).unwrap();
+            assert_sample(&parsed);
+            assert_eq!((parsed.endian, parsed.compression), (endian, comp));
+            assert_eq!(write_lay_bytes(&parsed, comp).unwrap(), packed);
+        }
+    }
+}
+
+#[test]
+fn write_field_overflow() {
+    let mut lay = parse_lay_bytes(&raw_lay()).unwrap();
+    lay.sprites[1].chunk_offset = usize::MAX;
+
+    match write_lay_bytes(&lay, Compression::Raw) {
+        Err(SgSpriteErr::FieldOverflow { field: "chunk offset", value: usize::MAX }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn raw_lay_with_zlib_like_header() {
+    // 376 sprites is 78 01 00 00, a valid zlib header
+    let mut data = Vec::new();
+    u32_le(376, &mut data);
+    u32_le(376, &mut data);
+    for i in 0..376u32 {
+        let t = if i == 0 { 0x00 } else { 0x20 };
+        data.extend_from_slice(&[i as u8, 0x00, 0x00, t]);
+        u32_le(i, &mut data);
+        u32_le(1, &mut data);
+    }
+    for i in 0..376 {
+        for &f in &[-32f32, -32., (i * 32 + 1) as f32, 1.] {
+            data.extend_from_slice(&f.to_le_bytes());
+        }
+    }
+
+    assert_eq!(detect_compression(&data[..8]), Compression::Zlib);
+    assert_eq!(detect_compression_len(&data[..8], data.len() as u64), Compression::Raw);
+
+    let check = |lay: ParsedLay| {
+        assert_eq!(lay.compression, Compression::Raw);
+        assert_eq!(lay.sprites.len(), 376);
+    };
+    check(parse_lay_bytes(&data).unwrap());
+    check(parse_lay(&mut Cursor::new(&data)).unwrap());
+    check(parse_lay_read(&data[..]).unwrap());
+    assert_eq!(decompress_lay(&data).unwrap(), (data.clone(), Compression::Raw));
+
+    // compressed file of the same lay is smaller than its raw lists
+    let lay = parse_lay_bytes(&data).unwrap();
+    let zlib = write_lay_bytes(&lay, Compression::Zlib).unwrap();
+    assert_eq!(parse_lay_read(&zlib[..]).unwrap().compression, Compression::Zlib);
+}
+
+#[test]
+fn parse_unknown_compression() {
+    let garbage = [0xFFu8; 64];
+    match parse_lay_bytes(&garbage) {
+        Err(SgSpriteErr::UnknownCompression) => (),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn write_preserves_raw_info_and_trailing() {
+    let mut orig = raw_lay();
+    orig[8 + 2] = 0x7F; // unexpected C byte of base sprite
+    orig.extend_from_slice(b"junk");
+
+    let lay = parse_lay_bytes(&orig).unwrap();
+    assert_eq!(lay.sprites[0].info, [0x01, 0x00, 0x7F, 0x00]);
+    assert_eq!(lay.trailing, b"junk");
+    assert_eq!(lay.warnings.len(), 1);
+    assert_eq!(write_lay_bytes(&lay, Compression::Raw).unwrap(), orig);
+}
+
+#[test]
+fn write_edited_sprites() {
+    let mut lay = parse_lay_bytes(&raw_lay()).unwrap();
+    lay.sprites[0].info[2] = 0x7F;
+    lay.sprites[0].id = 7;
+    lay.sprites[1].sprite_type = SpriteT::Overlay;
+
+    let written = parse_lay_bytes(&write_lay_bytes(&lay, Compression::Raw).unwrap()).unwrap();
+    // id is set, the rest is kept while the type is the same
+    assert_eq!(written.sprites[0].id, 7);
+    assert_eq!(written.sprites[0].info, [0x07, 0x00, 0x7F, 0x00]);
+    assert_eq!(written.sprites[1].sprite_type, SpriteT::Overlay);
+    assert_eq!(written.sprites[1].info, SpriteT::Overlay.info(0x02));
+
+    let dep = SpriteT::Dep { exact_type: 0x40, depends_on: 0x02 };
+    lay.sprites[1].sprite_type = dep;
+    let written = parse_lay_bytes(&write_lay_bytes(&lay, Compression::Raw).unwrap()).unwrap();
+    assert_eq!(written.sprites[1].sprite_type, dep);
+}
+
+#[test]
+fn validate_ranges() {
+    let mut lay = parse_lay_bytes(&raw_lay()).unwrap();
+    validate_lay(&lay).unwrap();
+    validate_source(&lay, (128, 64)).unwrap();
+
+    match validate_source(&lay, (64, 64)) {
+        Err(SgSpriteErr::SrcOutOfBounds { chunk: 2, x: 64, y: 0, .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    lay.sprites[1].chunk_count = 2;
+    match validate_lay(&lay) {
+        Err(SgSpriteErr::ChunkRange { sprite: 1, offset: 2, count: 2, total: 3 }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn draw_reports_sprite_index() {
+    let mut lay = parse_lay_bytes(&raw_lay()).unwrap();
+    lay.sprites[1].chunk_count = 2;
+
+    let graph = DepGraph::resolve_dep_graph(&lay);
+    let leaf = graph.get_leaf_sprites().next().unwrap();
+    let layers = graph.resolve_layers(leaf).unwrap();
+    assert_eq!(layers.iter().map(|n| n.index).collect::<Vec<_>>(), vec![1, 0]);
+
+    match draw_sprites(&mut draw_prep_bytes(&source_png()).unwrap(), &layers, 1, &lay) {
+        Err(SgSpriteErr::ChunkRange { sprite: 1, .. }) => (),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn check_anomalies() {
+    let mut lay = parse_lay_bytes(&raw_lay()).unwrap();
+    assert_eq!(check_lay(&lay, Some((128, 64))), vec![]);
+
+    lay.sprites[1].info[2] = 0x01;
+    lay.sprites[1].chunk_offset = 1;
+    lay.chunks[1].img_x = -16;
+
+    assert_eq!(
+        check_lay(&lay, Some((64, 64))),
+        vec![
+            Anomaly::OverlappingChunks { sprite: 0, chunks: (0, 1) },
+            Anomaly::UnexpectedHead { sprite: 1, info: [0x02, 0x00, 0x01, 0x20] },
+            Anomaly::UnusedChunks { first: 2, count: 1 },
+            Anomaly::ChunkOutOfSource { chunk: 2, x: 64, y: 0 },
+        ]
+    );
+}
+
+#[test]
+fn check_exit_code() {
+    let dir = std::path::Path::new("./target/test_check");
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::write(dir.join("CHECK_A.lay"), raw_lay()).unwrap();
+    std::fs::write(dir.join("CHECK_A.png"), source_png()).unwrap();
+    // no source png
+    std::fs::write(dir.join("CHECK_B.lay"), raw_lay()).unwrap();
+
+    let check = |names: &[&str]| {
+        let lay_files = names.iter().map(|n| dir.join(n)).collect();
+        lib_main(&Opts { lay_files, check: true, ..Opts::default() }).unwrap()
+    };
+
+    let summary = check(&["CHECK_A.lay"]);
+    assert_eq!((summary.anomalous, summary.exit_code()), (0, 0));
+    let summary = check(&["CHECK_A.lay", "CHECK_B.lay"]);
+    assert_eq!((summary.anomalous, summary.exit_code()), (1, 2));
+
+    // failed files are collected with their errors
+    std::fs::write(dir.join("CHECK_C.lay"), [0xFF; 4]).unwrap();
+    let summary = check(&["CHECK_A.lay", "CHECK_C.lay"]);
+    assert_eq!(summary.exit_code(), 2);
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, dir.join("CHECK_C.lay"));
+    assert!(matches!(summary.failed[0].1, SgSpriteErr::Truncated { .. }));
+}
+
+#[test]
+fn dump_annotates_fields() {
+    let mut lay = raw_lay();
+    lay.extend_from_slice(b"tail");
+
+    let zlib = write_lay_bytes(&parse_lay_bytes(&lay).unwrap(), Compression::Zlib).unwrap();
+    let mut out = Vec::new();
+    dump_lay(&zlib, &ParseOpts::default(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+
+    assert!(out.starts_with("# Zlib lay, "));
+    assert!(out.contains("00000000  02000000                          sprite_count = 2\n"));
+    assert!(out.contains("  chunk_count = 3\n"));
+    assert!(out.contains("sprite 1: A(id)=0x02 B=0x00 C=0x00 D=0x20 Sub\n"));
+    assert!(out.contains("-- trailing data (4 bytes)\n"));
+
+    let mut out = Vec::new();
+    dump_lay(&lay[..30], &ParseOpts::default(), &mut out).unwrap();
+    assert!(String::from_utf8(out).unwrap().ends_with("-- truncated, 2 bytes left\n"));
+}
+
+/// [`raw_lay`] as stored on PS3/Xbox 360: every field is a big-endian 4 byte word
+fn raw_lay_be() -> Vec<u8> {
+    raw_lay().chunks(4).flat_map(|w| w.iter().rev().copied().collect::<Vec<_>>()).collect()
+}
+
+#[test]
+fn parse_big_endian() {
+    let data = raw_lay_be();
+    assert_eq!(detect_endian(&data), Some(Endian::Big));
+    assert_eq!(detect_endian(&raw_lay()), Some(Endian::Little));
+    assert_eq!(detect_compression(&data), Compression::Raw);
+
+    let lay = parse_lay_bytes(&data).unwrap();
+    assert_sample(&lay);
+    assert_eq!(lay.endian, Endian::Big);
+    assert_eq!(lay.sprites[1].info, [0x02, 0x00, 0x00, 0x20]);
+
+    // roundtrip keeps the byte order
+    assert_eq!(write_lay_bytes(&lay, Compression::Raw).unwrap(), data);
+    let zlib = write_lay_bytes(&lay, Compression::Zlib).unwrap();
+    assert_eq!(parse_lay_bytes(&zlib).unwrap().endian, Endian::Big);
+
+    let mut out = Vec::new();
+    dump_lay(&data, &ParseOpts::default(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("# Raw lay, 80 bytes decompressed, Big endian\n"));
+    assert!(out.contains("00000000  00000002                          sprite_count = 2\n"));
+    assert!(out.contains("sprite 1: A(id)=0x02 B=0x00 C=0x00 D=0x20 Sub\n"));
+}
+
+#[test]
+fn endian_override() {
+    // 65536 or 256 sprites and no chunks are sane both ways, little-endian wins
+    let ambiguous = [0, 0, 1, 0, 0, 0, 0, 0];
+    assert_eq!(detect_endian(&ambiguous), Some(Endian::Little));
+    assert_eq!(detect_endian(&[0xFF; 8]), None);
+
+    let big = ParseOpts { endian: Some(Endian::Big), ..ParseOpts::default() };
+    assert_sample(&big.parse_lay_bytes(&raw_lay_be()).unwrap());
+    assert!(big.parse_lay_bytes(&raw_lay()).is_err());
+
+    let little = ParseOpts { endian: Some(Endian::Little), ..ParseOpts::default() };
+    assert!(little.parse_lay_bytes(&raw_lay_be()).is_err());
+    assert_eq!("big".parse::<Endian>(), Ok(Endian::Big));
+    assert!("middle".parse::<Endian>().is_err());
+}
+
+#[test]
+fn parse_limits() {
+    let limited = |limits: Limits| ParseOpts { limits, ..ParseOpts::default() };
+    let data = raw_lay();
+
+    let res = limited(Limits { sprites: 1, ..Limits::default() }).parse_lay_bytes(&data);
+    assert!(matches!(res, Err(SgSpriteErr::TooManySprites { count: 2, limit: 1 })));
+
+    let res = limited(Limits { chunks: 2, ..Limits::default() }).parse_lay_bytes(&data);
+    assert!(matches!(res, Err(SgSpriteErr::TooManyChunks { count: 3, limit: 2 })));
+
+    let res = limited(Limits { canvas: 63, ..Limits::default() }).parse_lay_bytes(&data);
+    assert!(matches!(res, Err(SgSpriteErr::CanvasTooLarge { w: 64, h: 64, limit: 63 })));
+
+    // exactly at the limit is fine
+    let exact = limited(Limits { decompressed: data.len() as u64, ..Limits::default() });
+    assert_sample(&exact.parse_lay_bytes(&data).unwrap());
+
+    // zeroes compress well, so the stream is much smaller than the limit
+    let mut bomb = parse_lay_bytes(&data).unwrap();
+    bomb.trailing = vec![0; 1 << 20];
+    let zlib = write_lay_bytes(&bomb, Compression::Zlib).unwrap();
+    assert!(zlib.len() < 4096);
+
+    let small = limited(Limits { decompressed: 4096, ..Limits::default() });
+    assert!(matches!(small.parse_lay_bytes(&zlib), Err(SgSpriteErr::TooLarge { limit: 4096 })));
+    assert!(matches!(small.decompress_lay(&zlib), Err(SgSpriteErr::TooLarge { limit: 4096 })));
+    assert!(matches!(small.parse_lay_read(&zlib[..]), Err(SgSpriteErr::TooLarge { .. })));
+    assert_eq!(parse_lay_bytes(&zlib).unwrap().trailing.len(), 1 << 20);
+}
+
+#[test]
+fn raised_limits() {
+    let mut big = parse_lay_bytes(&raw_lay()).unwrap();
+    let base = big.sprites[0].clone();
+    big.sprites.resize(70000, base);
+    let data = write_lay_bytes(&big, Compression::Raw).unwrap();
+
+    // counts over the default limits don't look like a raw lay
+    assert_eq!(detect_endian(&data), None);
+    assert!(parse_lay_bytes(&data).is_err());
+
+    let raised = ParseOpts { limits: Limits { sprites: 100_000, ..Limits::default() }, ..ParseOpts::default() };
+    assert_eq!(raised.detect_endian(&data), Some(Endian::Little));
+    assert_eq!(raised.detect_compression(&data), Compression::Raw);
+    assert_eq!(raised.parse_lay_bytes(&data).unwrap().sprites.len(), 70000);
+    assert_eq!(raised.parse_lay_read(&data[..]).unwrap().sprites.len(), 70000);
+}
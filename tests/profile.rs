@@ -0,0 +1,61 @@
+mod common;
+
+use common::*;
+use sg_sprite::profile::PROFILES;
+use sg_sprite::*;
+
+#[test]
+fn profile_by_id() {
+    assert_eq!(GameProfile::by_id("cc").unwrap().title, "Chaos;Child");
+    assert!(GameProfile::by_id("sg2").unwrap_err().contains("sg0-ps3"));
+
+    for p in PROFILES {
+        assert_eq!(PROFILES.iter().filter(|q| q.id == p.id).count(), 1, "{}", p.id);
+    }
+}
+
+#[test]
+fn profile_detection() {
+    let raw = parse_lay_bytes(&raw_lay()).unwrap();
+    // several PC releases have raw little-endian lays
+    assert!(detect_profile(&raw).is_none());
+
+    let zlib = parse_lay_bytes(&write_lay_bytes(&raw, Compression::Zlib).unwrap()).unwrap();
+    assert_eq!(detect_profile(&zlib).map(|p| p.id), Some("cc"));
+
+    let mut big = parse_lay_bytes(&raw_lay()).unwrap();
+    big.endian = Endian::Big;
+    let big = parse_lay_bytes(&write_lay_bytes(&big, Compression::Raw).unwrap()).unwrap();
+    assert_eq!(detect_profile(&big).map(|p| p.id), Some("sg0-ps3"));
+
+    // no game has big-endian zlib lays
+    let big_zlib = parse_lay_bytes(&write_lay_bytes(&big, Compression::Zlib).unwrap()).unwrap();
+    assert!(detect_profile(&big_zlib).is_none());
+}
+
+#[test]
+fn profile_parse_opts() {
+    let cc = GameProfile::by_id("cc").unwrap();
+    let opts = cc.parse_opts(ParseOpts { block_size: Some(16), ..ParseOpts::default() });
+    assert_eq!(opts.compression, Some(Compression::Zlib));
+    assert_eq!(opts.endian, Some(Endian::Little));
+    assert_eq!(opts.block_size, Some(16));
+
+    // compression is forced, raw lay isn't a zlib stream
+    assert!(opts.parse_lay_bytes(&raw_lay()).is_err());
+
+    // block size is detected per file
+    let o = Opts { game: Some(cc), ..Opts::default() };
+    assert_eq!(o.parse_opts().block_size, None);
+}
+
+#[test]
+fn screen_check() {
+    let mut lay = parse_lay_bytes(&raw_lay()).unwrap();
+    assert!(check::check_screen(&lay, (1280, 720)).is_empty());
+
+    // sub sprite chunk moved far right
+    lay.chunks[2].img_x = 700;
+    assert_eq!(check::check_screen(&lay, (1280, 720)), vec![Anomaly::OffScreen { sprite: 1 }]);
+    assert!(check::check_screen(&lay, (1920, 1080)).is_empty());
+}
@@ -43,10 +43,11 @@ fn validate_output() {
         dir: Some(out_dir.to_path_buf()),
         limit: None,
         lay_files,
-        dry_run: false
+        ..Opts::default()
     };
 
-    lib_main(&o).unwrap();
+    let summary = lib_main(&o).unwrap();
+    assert!(summary.failed.is_empty(), "failed: {:?}", summary.failed);
 
     for f in out_dir.read_dir().unwrap().map(|r| r.unwrap()) {
         let path = f.path();
@@ -78,3 +79,31 @@ fn validate_output() {
         panic!("Some reference hashes left ({})", ref_hashes.len());
     }
 }
+
+#[test]
+#[ignore]
+fn lay_roundtrip() {
+    let lay_ext = OsStr::new("lay");
+    let lay_files = Path::new(FILES_DIR)
+        .read_dir().unwrap()
+        .flat_map(|r| r.ok().map(|f| f.path()))
+        .filter(|f| f.extension() == Some(lay_ext));
+
+    for path in lay_files {
+        let orig = std::fs::read(&path).unwrap();
+        let lay = parse_lay_bytes(&orig).unwrap();
+        let written = write_lay_bytes(&lay, lay.compression).unwrap();
+        println!("Testing {}", path.display());
+
+        match lay.compression {
+            Compression::Raw => assert_eq!(written, orig, "raw {}", path.display()),
+            _ => {
+                // compressed streams may differ, decompressed data may not
+                let (orig_raw, _) = decompress_lay(&orig).unwrap();
+                let raw = write_lay_bytes(&lay, Compression::Raw).unwrap();
+                assert_eq!(raw, orig_raw, "compressed {}", path.display());
+                assert_eq!(decompress_lay(&written).unwrap().0, orig_raw, "recompressed {}", path.display());
+            }
+        }
+    }
+}
@@ -0,0 +1,467 @@
+mod common;
+
+use common::*;
+use image::RgbaImage;
+use sg_sprite::*;
+use std::fs;
+use std::path::Path;
+
+const TYPE_SWIZZLED: u32 = 0x0000_0000;
+const TYPE_LINEAR: u32 = 0x6000_0000;
+
+/// GXT v3 with a single texture
+fn gxt(ttype: u32, format: u32, w: u16, h: u16, data: &[u8]) -> Vec<u8> {
+    let mut gxt = b"GXT\0".to_vec();
+    for v in &[0x1000_0003, 1, 0x40, data.len() as u32, 0, 0, 0] {
+        u32_le(*v, &mut gxt);
+    }
+    for v in &[0x40, data.len() as u32, 0xFFFF_FFFF, 0, ttype, format] {
+        u32_le(*v, &mut gxt);
+    }
+    for v in &[w, h, 1, 0] {
+        gxt.extend_from_slice(&v.to_le_bytes());
+    }
+    gxt.extend_from_slice(data);
+    gxt
+}
+
+/// Index of `x, y` in Vita swizzled order, `w` and `h` are powers of two
+fn morton_index(x: usize, y: usize, w: usize, h: usize) -> usize {
+    let min = w.min(h);
+    let tile = if w > h { x / min } else { y / min };
+    let (x, y) = (x % min, y % min);
+
+    let mut code = 0;
+    for bit in 0..16 {
+        code |= (y >> bit & 1) << (bit * 2) | (x >> bit & 1) << (bit * 2 + 1);
+    }
+    tile * min * min + code
+}
+
+fn source_rgba() -> RgbaImage {
+    image::load_from_memory(&source_png()).unwrap().as_rgba8().unwrap().clone()
+}
+
+#[test]
+fn gxt_rgba() {
+    let src = source_rgba();
+    let (w, h) = (src.width() as usize, src.height() as usize);
+
+    // ABGR is R, G, B, A in memory
+    let mut swizzled = vec![0u8; w * h * 4];
+    for (x, y, px) in src.enumerate_pixels() {
+        let at = morton_index(x as usize, y as usize, w, h) * 4;
+        swizzled[at..at + 4].copy_from_slice(&px.0);
+    }
+    let img = decode_gxt(&gxt(TYPE_SWIZZLED, 0x0C00_0000, 128, 64, &swizzled)).unwrap();
+    assert_eq!(img, src);
+
+    // ARGB is B, G, R, A in memory
+    let linear: Vec<u8> = src.pixels().flat_map(|p| vec![p[2], p[1], p[0], p[3]]).collect();
+    let img = decode_gxt(&gxt(TYPE_LINEAR, 0x0C00_1000, 128, 64, &linear)).unwrap();
+    assert_eq!(img, src);
+
+    // renders the same as the png
+    let lay = parse_lay_bytes(&raw_lay()).unwrap();
+    let mut prep = draw_prep_bytes(&gxt(TYPE_SWIZZLED, 0x0C00_0000, 128, 64, &swizzled)).unwrap();
+    let graph = DepGraph::resolve_dep_graph(&lay);
+    let leaf = graph.get_leaf_sprites().next().unwrap();
+    let sprite = draw_sprites(&mut prep, &graph.resolve_layers(leaf).unwrap(), 1, &lay).unwrap();
+    assert_eq!(sprite.get_pixel(40, 8).0, [64, 0, 0, 255]);
+}
+
+#[test]
+fn gxt_dxt() {
+    // red and blue endpoints, indices per row: 0 0 0 0 / 1 1 1 1 / 2 2 2 2 / 3 3 3 3
+    let opaque = [0x00, 0xF8, 0x1F, 0x00, 0x00, 0x55, 0xAA, 0xFF];
+    let img = decode_gxt(&gxt(TYPE_LINEAR, 0x8500_0000, 4, 4, &opaque)).unwrap();
+    assert_eq!(img.get_pixel(0, 0).0, [255, 0, 0, 255]);
+    assert_eq!(img.get_pixel(1, 1).0, [0, 0, 255, 255]);
+    assert_eq!(img.get_pixel(2, 2).0, [170, 0, 85, 255]);
+    assert_eq!(img.get_pixel(3, 3).0, [85, 0, 170, 255]);
+
+    // c0 < c1 switches BC1 to 3 colors and transparent black
+    let transparent = [0x1F, 0x00, 0x00, 0xF8, 0x00, 0x55, 0xAA, 0xFF];
+    let img = decode_gxt(&gxt(TYPE_LINEAR, 0x8500_0000, 4, 4, &transparent)).unwrap();
+    assert_eq!(img.get_pixel(2, 2).0, [127, 0, 127, 255]);
+    assert_eq!(img.get_pixel(3, 3).0, [0, 0, 0, 0]);
+
+    // BC3, 8x8 swizzled: the third block is right of the first one and has alpha 0x80
+    let mut blocks = Vec::new();
+    for i in 0..4 {
+        let alpha = if i == 2 { 0x80 } else { 0xFF };
+        blocks.extend_from_slice(&[alpha, alpha, 0, 0, 0, 0, 0, 0]);
+        blocks.extend_from_slice(&opaque);
+    }
+    let img = decode_gxt(&gxt(TYPE_SWIZZLED, 0x8700_0000, 8, 8, &blocks)).unwrap();
+    assert_eq!(img.get_pixel(4, 0).0, [255, 0, 0, 0x80]);
+    assert_eq!(img.get_pixel(0, 4).0, [255, 0, 0, 0xFF]);
+
+    match decode_gxt(&gxt(TYPE_LINEAR, 0x8500_0000, 8, 8, &opaque)) {
+        Err(SgSpriteErr::BadTexture(msg)) => assert!(msg.contains("BC1"), "{}", msg),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn gxt_pvrtc() {
+    // 8x8 4bpp is 2x2 blocks, color A is opaque white, color B is opaque black
+    let block = |mods: u32, mode: u32| {
+        let colors = 0x8000_0000 | 0xFFFE | mode;
+        [mods.to_le_bytes(), u32::to_le_bytes(colors)].concat()
+    };
+    let pvrtc = |mods: u32, mode: u32| (0..4).flat_map(|_| block(mods, mode)).collect::<Vec<_>>();
+
+    let img = decode_gxt(&gxt(TYPE_SWIZZLED, 0x8100_0000, 8, 8, &pvrtc(0, 0))).unwrap();
+    assert!(img.pixels().all(|p| p.0 == [255, 255, 255, 255]));
+
+    let img = decode_gxt(&gxt(TYPE_SWIZZLED, 0x8100_0000, 8, 8, &pvrtc(0xFFFF_FFFF, 0))).unwrap();
+    assert!(img.pixels().all(|p| p.0 == [0, 0, 0, 255]));
+
+    // modulation 1 of 3 is 3/8 of color B
+    let img = decode_gxt(&gxt(TYPE_SWIZZLED, 0x8100_0000, 8, 8, &pvrtc(0x5555_5555, 0))).unwrap();
+    assert!(img.pixels().all(|p| p.0 == [159, 159, 159, 255]));
+
+    // punch-through mode: modulation 2 is half blend with zero alpha
+    let img = decode_gxt(&gxt(TYPE_SWIZZLED, 0x8100_0000, 8, 8, &pvrtc(0xAAAA_AAAA, 1))).unwrap();
+    assert!(img.pixels().all(|p| p.0 == [127, 127, 127, 0]));
+
+    // 2bpp direct modulation, 16x8 is 2x2 blocks
+    let img = decode_gxt(&gxt(TYPE_SWIZZLED, 0x8000_0000, 16, 8, &pvrtc(0xFFFF_0000, 0))).unwrap();
+    assert_eq!(img.get_pixel(0, 0).0, [255, 255, 255, 255]);
+    assert_eq!(img.get_pixel(0, 3).0, [0, 0, 0, 255]);
+}
+
+#[test]
+fn gxt_malformed() {
+    for (data, part) in &[
+        (b"GXT\0".to_vec(), "truncated"),
+        (gxt(TYPE_LINEAR, 0x0C00_0000, 4, 4, &[0; 63]), "short"),
+        (gxt(TYPE_LINEAR, 0x0500_0000, 4, 4, &[0; 64]), "format"),
+        (gxt(0x8000_0000, 0x0C00_0000, 4, 4, &[0; 64]), "type"),
+    ] {
+        match decode_gxt(data) {
+            Err(SgSpriteErr::BadTexture(msg)) => assert!(msg.contains(part), "{}", msg),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}
+
+#[test]
+fn gxt_source_pairing() {
+    let dir = Path::new("./target/test_gxt/");
+    if dir.exists() {
+        fs::remove_dir_all(dir).unwrap();
+    }
+    fs::create_dir_all(dir).unwrap();
+
+    let src = source_rgba();
+    let linear: Vec<u8> = src.pixels().flat_map(|p| p.0.to_vec()).collect();
+    fs::write(dir.join("CRS_A.lay"), raw_lay()).unwrap();
+    fs::write(dir.join("CRS_A.gxt"), gxt(TYPE_LINEAR, 0x0C00_0000, 128, 64, &linear)).unwrap();
+
+    let anomalies = check_file(&dir.join("CRS_A.lay"), &ParseOpts::default()).unwrap();
+    assert!(anomalies.is_empty(), "{:?}", anomalies);
+    assert_eq!(source_dimensions(&fs::read(dir.join("CRS_A.gxt")).unwrap()).unwrap(), (128, 64));
+}
+
+/// DDS with the given pixel format
+fn dds(pf_flags: u32, fourcc: &[u8; 4], bit_count: u32, masks: [u32; 4], w: u32, h: u32, data: &[u8]) -> Vec<u8> {
+    let mut dds = b"DDS ".to_vec();
+    for v in &[0x7C, 0x1007, h, w, 0, 0, 1] {
+        u32_le(*v, &mut dds);
+    }
+    dds.extend_from_slice(&[0; 0x2C]);
+    u32_le(0x20, &mut dds);
+    u32_le(pf_flags, &mut dds);
+    dds.extend_from_slice(fourcc);
+    u32_le(bit_count, &mut dds);
+    for m in &masks {
+        u32_le(*m, &mut dds);
+    }
+    dds.extend_from_slice(&[0; 0x14]);
+    dds.extend_from_slice(data);
+    dds
+}
+
+/// DDS with the DX10 header
+fn dds_dx10(dxgi: u32, w: u32, h: u32, data: &[u8]) -> Vec<u8> {
+    let mut dx10 = Vec::new();
+    for v in &[dxgi, 3, 0, 1, 0] {
+        u32_le(*v, &mut dx10);
+    }
+    dx10.extend_from_slice(data);
+    dds(0x4, b"DX10", 0, [0; 4], w, h, &dx10)
+}
+
+/// 128-bit block from LSB-first `(value, bits)` fields
+fn bit_block(fields: &[(u32, u32)]) -> Vec<u8> {
+    let (mut block, mut at) = (0u128, 0);
+    for (v, n) in fields {
+        block |= (*v as u128) << at;
+        at += n;
+    }
+    assert!(at <= 128);
+    block.to_le_bytes().to_vec()
+}
+
+#[test]
+fn dds_rgba() {
+    let src = source_rgba();
+
+    let bgra: Vec<u8> = src.pixels().flat_map(|p| vec![p[2], p[1], p[0], p[3]]).collect();
+    let masks = [0xFF_0000, 0xFF00, 0xFF, 0xFF00_0000];
+    let img = decode_dds(&dds(0x41, &[0; 4], 32, masks, 128, 64, &bgra)).unwrap();
+    assert_eq!(img, src);
+
+    let rgba: Vec<u8> = src.pixels().flat_map(|p| p.0.to_vec()).collect();
+    assert_eq!(decode_dds(&dds_dx10(28, 128, 64, &rgba)).unwrap(), src);
+
+    // 24 bit without alpha
+    let bgr: Vec<u8> = src.pixels().flat_map(|p| vec![p[2], p[1], p[0]]).collect();
+    let img = decode_dds(&dds(0x40, &[0; 4], 24, masks, 128, 64, &bgr)).unwrap();
+    assert_eq!(img, src);
+
+    let data = dds(0x41, &[0; 4], 32, masks, 128, 64, &bgra);
+    assert_eq!(source_dimensions(&data).unwrap(), (128, 64));
+    let lay = parse_lay_bytes(&raw_lay()).unwrap();
+    let mut prep = draw_prep_bytes(&data).unwrap();
+    let graph = DepGraph::resolve_dep_graph(&lay);
+    let leaf = graph.get_leaf_sprites().next().unwrap();
+    let sprite = draw_sprites(&mut prep, &graph.resolve_layers(leaf).unwrap(), 1, &lay).unwrap();
+    assert_eq!(sprite.get_pixel(8, 40).0, [128, 0, 0, 255]);
+}
+
+#[test]
+fn dds_dxt() {
+    // BC3 with constant alpha 0x40 and the BC1 part of `gxt_dxt`
+    let block = [0x40, 0x40, 0, 0, 0, 0, 0, 0, 0x00, 0xF8, 0x1F, 0x00, 0x00, 0x55, 0xAA, 0xFF];
+    let img = decode_dds(&dds(0x4, b"DXT5", 0, [0; 4], 4, 4, &block)).unwrap();
+    assert_eq!(img.get_pixel(0, 0).0, [255, 0, 0, 0x40]);
+    assert_eq!(img.get_pixel(3, 3).0, [85, 0, 170, 0x40]);
+
+    // 6x2 is cropped from 2 blocks
+    let img = decode_dds(&dds(0x4, b"DXT1", 0, [0; 4], 6, 2, &[&block[8..], &block[8..]].concat())).unwrap();
+    assert_eq!(img.dimensions(), (6, 2));
+    assert_eq!(img.get_pixel(5, 1).0, [0, 0, 255, 255]);
+}
+
+#[test]
+fn dds_bc7() {
+    // mode 6: white and black endpoints, indices 0, 8 and 15
+    let mut fields = vec![(0x40, 7)];
+    fields.extend_from_slice(&[(127, 7), (0, 7), (127, 7), (0, 7), (127, 7), (0, 7), (127, 7), (0, 7)]);
+    fields.extend_from_slice(&[(1, 1), (0, 1), (0, 3), (8, 4)]);
+    fields.extend((2..16).map(|_| (15, 4)));
+    let img = decode_dds(&dds_dx10(98, 4, 4, &bit_block(&fields))).unwrap();
+    assert_eq!(img.get_pixel(0, 0).0, [255, 255, 255, 255]);
+    assert_eq!(img.get_pixel(1, 0).0, [120, 120, 120, 120]);
+    assert_eq!(img.get_pixel(3, 3).0, [0, 0, 0, 0]);
+
+    // mode 1, partition 13: top half is subset 0 (red), bottom half is subset 1 (blue)
+    let mut fields = vec![(0x2, 2), (13, 6)];
+    fields.extend_from_slice(&[(63, 6), (63, 6), (0, 6), (0, 6)]); // R
+    fields.extend_from_slice(&[(0, 6), (0, 6), (0, 6), (0, 6)]); // G
+    fields.extend_from_slice(&[(0, 6), (0, 6), (63, 6), (63, 6)]); // B
+    fields.extend_from_slice(&[(0, 1), (0, 1), (0, 46)]);
+    let img = decode_dds(&dds_dx10(98, 4, 4, &bit_block(&fields))).unwrap();
+    assert_eq!(img.get_pixel(3, 1).0, [253, 0, 0, 255]);
+    assert_eq!(img.get_pixel(0, 2).0, [0, 0, 253, 255]);
+
+    // mode 5, rotation 1 swaps red and alpha
+    let mut fields = vec![(0x20, 6), (1, 2)];
+    fields.extend_from_slice(&[(127, 7), (127, 7), (0, 7), (0, 7), (0, 7), (0, 7), (0x80, 8), (0x80, 8)]);
+    let img = decode_dds(&dds_dx10(98, 4, 4, &bit_block(&fields))).unwrap();
+    assert_eq!(img.get_pixel(2, 2).0, [0x80, 0, 0, 255]);
+
+    // reserved mode decodes to transparent black
+    let img = decode_dds(&dds_dx10(98, 4, 4, &[0; 16])).unwrap();
+    assert_eq!(img.get_pixel(0, 0).0, [0, 0, 0, 0]);
+}
+
+#[test]
+fn dds_malformed() {
+    for (data, part) in &[
+        (b"DDS ".to_vec(), "truncated"),
+        (dds(0x4, b"ATI2", 0, [0; 4], 4, 4, &[0; 16]), "ATI2"),
+        (dds_dx10(2, 4, 4, &[0; 256]), "DXGI format 2"),
+        (dds(0x4, b"DXT1", 0, [0; 4], 8, 8, &[0; 16]), "BC1"),
+        (dds(0x41, &[0; 4], 32, [0xFF, 0xFF00, 0xFF_0000, 0], 4, 4, &[0; 60]), "RGB data"),
+        // sizes overflow
+        (dds(0x4, b"DXT5", 0, [0; 4], u32::MAX, u32::MAX, &[0; 16]), "BC3 image"),
+        (dds(0x41, &[0; 4], 32, [0xFF, 0xFF00, 0xFF_0000, 0], u32::MAX, u32::MAX, &[0; 16]), "RGB image"),
+    ] {
+        match decode_dds(data) {
+            Err(SgSpriteErr::BadTexture(msg)) => assert!(msg.contains(part), "{}", msg),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}
+
+/// BNTX with a single texture, `layout` is the block height log2
+fn bntx(format: u32, tile_mode: u16, layout: u32, w: u32, h: u32, data: &[u8]) -> Vec<u8> {
+    let mut bntx = b"BNTX\0\0\0\0".to_vec();
+    u32_le(0x0004_0000, &mut bntx);
+    bntx.extend_from_slice(&[0xFF, 0xFE, 0x0C, 0x40]);
+    bntx.resize(0x20, 0);
+
+    bntx.extend_from_slice(b"NX  ");
+    u32_le(1, &mut bntx);
+    bntx.extend_from_slice(&0x40u64.to_le_bytes());
+    bntx.resize(0x40, 0);
+    bntx.extend_from_slice(&0x50u64.to_le_bytes());
+    bntx.resize(0x50, 0);
+
+    let brti = bntx.len();
+    bntx.extend_from_slice(b"BRTI");
+    bntx.resize(brti + 0x12, 0);
+    bntx.extend_from_slice(&tile_mode.to_le_bytes());
+    bntx.resize(brti + 0x1C, 0);
+    for v in &[format, 0, w, h, 1, 1, layout] {
+        u32_le(*v, &mut bntx);
+    }
+    bntx.resize(brti + 0x50, 0);
+    u32_le(data.len() as u32, &mut bntx);
+    bntx.resize(brti + 0x70, 0);
+    bntx.extend_from_slice(&0xF0u64.to_le_bytes());
+    bntx.resize(0xF0, 0);
+
+    bntx.extend_from_slice(&0x100u64.to_le_bytes());
+    bntx.resize(0x100, 0);
+    bntx.extend_from_slice(data);
+    bntx
+}
+
+/// Offset of the `x, y` unit in block-linear data `width` units wide
+fn block_linear_offset(x: usize, y: usize, width: usize, unit_sz: usize, block_height: usize) -> usize {
+    let width_gobs = (width * unit_sz).div_ceil(64);
+    let xb = x * unit_sz;
+    let gob = y / (8 * block_height) * width_gobs * 512 * block_height
+        + xb / 64 * 512 * block_height
+        + y % (8 * block_height) / 8 * 512;
+    gob + xb % 64 / 32 * 256 + y % 8 / 2 * 64 + xb % 32 / 16 * 32 + y % 2 * 16 + xb % 16
+}
+
+#[test]
+fn bntx_rgba() {
+    let src = source_rgba();
+
+    // block height of 2 GOBs, 128x64 is 8 GOBs wide and 4 blocks high
+    let mut swizzled = vec![0u8; 128 * 64 * 4];
+    for (x, y, px) in src.enumerate_pixels() {
+        let at = block_linear_offset(x as usize, y as usize, 128, 4, 2);
+        swizzled[at..at + 4].copy_from_slice(&px.0);
+    }
+    let data = bntx(0x0B01, 0, 1, 128, 64, &swizzled);
+    assert_eq!(decode_bntx(&data).unwrap(), src);
+    assert_eq!(source_dimensions(&data).unwrap(), (128, 64));
+
+    let bgra: Vec<u8> = src.pixels().flat_map(|p| vec![p[2], p[1], p[0], p[3]]).collect();
+    assert_eq!(decode_bntx(&bntx(0x0C06, 1, 0, 128, 64, &bgra)).unwrap(), src);
+
+    // BC1 blocks are 8 bytes units of the same layout
+    let block = [0x00, 0xF8, 0x1F, 0x00, 0x00, 0x55, 0xAA, 0xFF];
+    let mut swizzled = vec![0u8; 512];
+    let at = block_linear_offset(1, 0, 2, 8, 1);
+    swizzled[at..at + 8].copy_from_slice(&block);
+    let img = decode_bntx(&bntx(0x1A01, 0, 0, 8, 4, &swizzled)).unwrap();
+    assert_eq!(img.get_pixel(4, 0).0, [255, 0, 0, 255]);
+    assert_eq!(img.get_pixel(0, 0).0, [0, 0, 0, 255]);
+}
+
+#[test]
+fn bntx_astc() {
+    // void extent blocks (constant color) of 8x8 ASTC, 12x8 is two blocks
+    let void_extent = |c: [u16; 4]| {
+        let mut b = vec![0xFC, 0xFD, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        c.iter().for_each(|v| b.extend_from_slice(&v.to_le_bytes()));
+        b
+    };
+    let data = [void_extent([0xFFFF, 0x8000, 0, 0xFFFF]), void_extent([0, 0, 0xFFFF, 0x4000])].concat();
+    let img = decode_bntx(&bntx(0x3401, 1, 0, 12, 8, &data)).unwrap();
+    assert_eq!(img.get_pixel(7, 7).0, [255, 128, 0, 255]);
+    assert_eq!(img.get_pixel(11, 0).0, [0, 0, 255, 64]);
+
+    // 4x4: 4x4 grid of 2-bit weights, luminance endpoints 0 and 255
+    let low = 0x42u128 | 255 << 25; // 1 partition, CEM 0, color values 0 and 255 from bit 17
+    let weights: u128 = (0..16).map(|i| (i % 4) << (i * 2)).sum();
+    let block = (low | weights.reverse_bits()).to_le_bytes();
+    let img = decode_bntx(&bntx(0x2D01, 1, 0, 4, 4, &block)).unwrap();
+    let row: Vec<_> = (0..4).map(|x| img.get_pixel(x, 2).0).collect();
+    assert_eq!(row, vec![[0, 0, 0, 255], [84, 84, 84, 255], [171, 171, 171, 255], [255, 255, 255, 255]]);
+
+    // reserved block mode decodes to the error color
+    let img = decode_bntx(&bntx(0x2D01, 1, 0, 4, 4, &[0; 16])).unwrap();
+    assert_eq!(img.get_pixel(0, 0).0, [255, 0, 255, 255]);
+}
+
+/// Known-answer 4x4 ASTC blocks, endpoints and weights are chosen so that the
+/// decoded colors can be checked against the spec by hand
+#[test]
+fn astc_blocks() {
+    let decode = |block: u128| decode_bntx(&bntx(0x2D01, 1, 0, 4, 4, &block.to_le_bytes())).unwrap();
+    // weight grid is 4x4 too, with the same weight in every row
+    let columns = |block: u128, row: [[u8; 4]; 4]| {
+        for (x, y, px) in decode(block).enumerate_pixels() {
+            assert_eq!(px.0, row[x as usize], "block {:#X} at {},{}", block, x, y);
+        }
+    };
+
+    // 1 partition, RGB endpoints 10,20,30 and 200,150,100 as 8-bit values,
+    // 3-level weights (trits) 0, 32, 64, 32
+    columns(
+        0xCC99_6780_0000_0000_C83D_2C29_9015_0051,
+        [[10, 20, 30, 255], [105, 85, 65, 255], [200, 150, 100, 255], [105, 85, 65, 255]],
+    );
+
+    // 1 partition, luminance endpoints 40 and 220, 5-level weights (quints) 0, 16, 48, 64
+    columns(
+        0x164A_E7F1_6400_0000_0000_0001_B850_0052,
+        [[40, 40, 40, 255], [85, 85, 85, 255], [175, 175, 175, 255], [220, 220, 220, 255]],
+    );
+
+    // 1 partition, RGBA endpoints as 192-level values (trits + 6 bits): 247,25,120,42 and 82,190,133,130,
+    // 3-bit weights 0, 18, 46, 64
+    columns(
+        0x0AF0_AF0A_F0AF_3FCA_5EBC_6199_500B_8053,
+        [[247, 25, 120, 42], [201, 71, 124, 67], [128, 144, 129, 105], [82, 190, 133, 130]],
+    );
+
+    // 2 partitions (seed 3), RGB endpoints as 40-level values (quints + 3 bits):
+    // 13,6,45 and 216,138,177 for partition 0, 52,97,123 and 255,132,91 for partition 1,
+    // 2-bit weights 0, 21, 43, 64
+    let img = decode(0x2727_2727_0437_E60B_5593_E85A_1000_6842);
+    let expected = [
+        [[52, 97, 123, 255], [79, 49, 88, 255], [189, 120, 101, 255], [255, 132, 91, 255]],
+        [[13, 6, 45, 255], [79, 49, 88, 255], [189, 120, 101, 255], [255, 132, 91, 255]],
+        [[13, 6, 45, 255], [119, 108, 112, 255], [189, 120, 101, 255], [216, 138, 177, 255]],
+        [[13, 6, 45, 255], [119, 108, 112, 255], [189, 120, 101, 255], [216, 138, 177, 255]],
+    ];
+    for (x, y, px) in img.enumerate_pixels() {
+        assert_eq!(px.0, expected[y as usize][x as usize], "at {},{}", x, y);
+    }
+}
+
+#[test]
+fn bntx_malformed() {
+    let mut big_endian = bntx(0x0B01, 1, 0, 1, 1, &[0; 4]);
+    big_endian[0xC..0xE].copy_from_slice(&[0xFE, 0xFF]);
+
+    for (data, part) in &[
+        (b"BNTX".to_vec(), "truncated"),
+        (big_endian, "big-endian"),
+        (bntx(0x0201, 1, 0, 4, 4, &[0; 64]), "format 0x0201"),
+        (bntx(0x0B01, 0, 0, 4, 4, &[0; 64]), "block-linear"),
+        (bntx(0x1A01, 1, 0, 8, 8, &[0; 8]), "BC1"),
+        (bntx(0x2D01, 1, 0, 8, 8, &[0; 16]), "ASTC 4x4"),
+        // sizes overflow
+        (bntx(0x0B01, 1, 0, u32::MAX, u32::MAX, &[0; 64]), "too large"),
+        (bntx(0x0B01, 0, 0, u32::MAX, u32::MAX, &[0; 64]), "too large"),
+        (bntx(0x2D01, 1, 0, u32::MAX, u32::MAX, &[0; 64]), "too large"),
+    ] {
+        match decode_bntx(data) {
+            Err(SgSpriteErr::BadTexture(msg)) => assert!(msg.contains(part), "{}", msg),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}